@@ -0,0 +1,234 @@
+//! Known errors which should not cause a check to fail.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{portable_path, CheckError, Link, LinkError, Report};
+
+/// The version of the baseline file format.
+const BASELINE_VERSION: u32 = 1;
+
+/// A set of errors which were already known when the baseline was written.
+///
+/// Errors are identified by the directory and file they occur in, their kind, and their link,
+/// but not by their position, so that unrelated changes to a file don't invalidate the baseline.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    version: u32,
+    entries: BTreeSet<BaselineEntry>,
+}
+
+/// A single known error.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    /// The name of the documentation directory, e.g. the name of the crate.
+    pub directory: String,
+    /// The path of the HTML file, relative to `directory`.
+    pub file: String,
+    /// The kind of the error, as given by [`CheckError::kind`].
+    pub kind: String,
+    /// The broken link, including the fragment if it was missing. If there is no link, this is the error message.
+    pub link: String,
+}
+
+impl BaselineEntry {
+    pub fn new(root: &Path, file: &Path, error: &CheckError) -> Self {
+        let shorten = |path: &Path| {
+            let path = path.strip_prefix(root).unwrap_or(path);
+            if path.is_absolute() {
+                path.display().to_string()
+            } else {
+                portable_path(path)
+            }
+        };
+        let link = match error {
+            CheckError::File(path) => shorten(path),
+            CheckError::Fragment(link, fragment, _) => {
+                let link = match link {
+                    Link::File(path) => shorten(path),
+                    Link::Http(url) => url.to_string(),
+                };
+                format!("{}#{}", link, fragment)
+            }
+            _ => error.link().unwrap_or_else(|| error.to_string()),
+        };
+        BaselineEntry {
+            directory: root
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            file: shorten(file),
+            kind: error.kind().to_owned(),
+            link,
+        }
+    }
+}
+
+impl fmt::Display for BaselineEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}: {} {}",
+            self.directory, self.file, self.kind, self.link
+        )
+    }
+}
+
+impl Baseline {
+    /// Record every error in `report`.
+    pub fn from_report(report: &Report) -> Self {
+        let entries = report
+            .errors()
+            .map(|(root, file, e)| BaselineEntry::new(root, &file.path, &e.error))
+            .collect();
+        Baseline {
+            version: BASELINE_VERSION,
+            entries,
+        }
+    }
+
+    /// Read a baseline written by [`Baseline::save`].
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let baseline: Baseline = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if baseline.version != BASELINE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported baseline version {}", baseline.version),
+            ));
+        }
+        Ok(baseline)
+    }
+
+    /// Write the baseline to `path` as JSON, with entries in a stable order.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, entry: &BaselineEntry) -> bool {
+        self.entries.contains(entry)
+    }
+
+    /// Remove every error in the baseline from `report`.
+    ///
+    /// Returns the entries of the baseline which no longer occur, i.e. which have been fixed.
+    pub fn filter(&self, report: &mut Report) -> Vec<BaselineEntry> {
+        let mut seen = BTreeSet::new();
+        report.retain(|root, path, e: &LinkError| {
+            let entry = BaselineEntry::new(root, path, &e.error);
+            let known = self.entries.contains(&entry);
+            if known {
+                seen.insert(entry);
+            }
+            !known
+        });
+        self.entries.difference(&seen).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{Baseline, BaselineEntry};
+    use crate::{CheckError, FileError, Link, Report};
+
+    fn report(errors: Vec<CheckError>) -> Report {
+        let mut report = Report::new();
+        report.add_dir(
+            Path::new("/doc/my_crate"),
+            vec![FileError {
+                path: PathBuf::from("index.html"),
+                errors: errors.into_iter().map(Into::into).collect(),
+            }],
+        );
+        report
+    }
+
+    #[test]
+    fn test_baseline() {
+        let old = report(vec![
+            CheckError::File(PathBuf::from("fn.old.html")),
+            CheckError::Fragment(
+                Link::File(PathBuf::from("/doc/my_crate/index.html")),
+                "missing".into(),
+                None,
+            ),
+        ]);
+        let baseline = Baseline::from_report(&old);
+        assert_eq!(baseline.len(), 2);
+
+        let mut new = report(vec![
+            CheckError::File(PathBuf::from("fn.new.html")),
+            CheckError::Fragment(
+                Link::File(PathBuf::from("index.html")),
+                "missing".into(),
+                None,
+            ),
+        ]);
+        let fixed = baseline.filter(&mut new);
+        assert_eq!(fixed.len(), 1);
+        assert_eq!(
+            fixed[0].to_string(),
+            "my_crate/index.html: missing-file fn.old.html"
+        );
+
+        let remaining: Vec<_> = new.errors().map(|(_, _, e)| e.error.to_string()).collect();
+        assert_eq!(
+            remaining,
+            vec!["Linked file at path fn.new.html does not exist!"]
+        );
+
+        let mut old = old;
+        assert!(baseline.filter(&mut old).is_empty());
+        assert!(!old.has_errors());
+    }
+
+    #[test]
+    fn test_nested_paths() {
+        let root = Path::new("/doc/my_crate");
+        let file = root.join("module").join("struct.Foo.html");
+        let target = root.join("module").join("fn.bar.html");
+        let entry = BaselineEntry::new(root, &file, &CheckError::File(target));
+        assert_eq!(entry.directory, "my_crate");
+        assert_eq!(entry.file, "module/struct.Foo.html");
+        assert_eq!(entry.link, "module/fn.bar.html");
+
+        let entry = BaselineEntry::new(
+            root,
+            &Path::new("module").join("index.html"),
+            &CheckError::Fragment(Link::File(file), "impl".into(), None),
+        );
+        assert_eq!(entry.file, "module/index.html");
+        assert_eq!(entry.link, "module/struct.Foo.html#impl");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("deadlinks-baseline-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("baseline.json");
+
+        let baseline = Baseline::from_report(&report(vec![CheckError::IntraDocLink(
+            "[<code>x</code>]".into(),
+        )]));
+        baseline.save(&path).unwrap();
+        assert_eq!(Baseline::load(&path).unwrap(), baseline);
+
+        std::fs::write(&path, r#"{"version": 2, "entries": []}"#).unwrap();
+        assert!(Baseline::load(&path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,17 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::process::{self, Command};
 
-use cargo_metadata::{Message, MetadataCommand};
+use cargo_metadata::camino::Utf8PathBuf;
+use cargo_metadata::{Message, Metadata, MetadataCommand, PackageId};
 use serde_derive::Deserialize;
 
-use cargo_deadlinks::{walk_dir, CheckContext, HttpCheck};
+use cargo_deadlinks::{
+    check_dir, check_markdown, walk_dir, Book, CheckContext, Config, HttpCheck, LinkKind, Report,
+};
 
 mod shared;
 
@@ -15,51 +20,146 @@ Check your package's documentation for dead links.
 
 Usage:
     cargo deadlinks [--dir <directory>] [--cargo-dir <directory>] [options] [-- <CARGO_ARGS>]
+    cargo deadlinks --book <directory> [options]
 
 Options:
     -h --help               Print this message.
     --dir                   Specify a directory to check (default is all paths that have documentation generated by cargo).
+    --book <directory>      Check the mdBook in this directory instead of the documentation. The book is built
+                            with `mdbook build` first, unless `--no-build` is passed.
     --cargo-dir             Specify which directory to look in for the Cargo manifest (default is the current directory).
+    --config <file>         Read settings from this file (default is deadlinks.toml at the root of the workspace, if it exists).
     --check-http            Check 'http' and 'https' scheme links.
     --forbid-http           Give an error if HTTP links are found. This is incompatible with --check-http.
     --check-intra-doc-links Check for broken intra-doc links.
     --ignore-fragments      Don't check URL fragments.
+    --link-kinds <kinds>    Only check these kinds of links, separated by commas (default is all of them).
+                            Possible kinds: hyperlink, media, resource, script, embed, form.
+    --ignore-link <pattern> Don't check links matching this glob, or regex if it starts with 'regex:'. Can be repeated.
+                            Local files are matched relative to the documentation root, other links by their URL.
+    --ignore-file <pattern> Don't check HTML or Markdown files or directories matching this pattern. Can be repeated.
+    --base-url <url>        The URL the documentation is deployed at, e.g. 'https://example.com/docs/'. Links to
+                            the same site are checked against the local files instead of over HTTP.
+    --site-root <path>      The path the documentation is served under, e.g. '/docs/' (default is the path of
+                            --base-url, or '/'). Absolute paths are resolved relative to it; other absolute paths are skipped.
+    --orphan-pages          Give an error for pages which can't be reached by following links from the index.html of
+                            each crate's documentation, e.g. stale files left over from previous builds.
+    --entry-point <path>    With --orphan-pages, also follow links from this page. Can be repeated.
+    --format <format>       How to print errors: 'human' (the default), 'json' or 'sarif'.
+    --baseline <file>       Only fail on errors which are not in this baseline, and list the ones that were fixed.
+    --write-baseline <file> Record all errors in a baseline file, to be passed to `--baseline` later.
+    --fix                   Rewrite links in doc comments which permanently redirect, or point to items which were moved.
+    --dry-run               With --fix, print the changes as a diff instead of writing them.
+    --max-http-requests <n> How many HTTP requests to send at once (default is 16).
+    --max-http-requests-per-host <n>
+                            How many HTTP requests to send to a single host at once (default is 4).
+    --http-rate-limit <n>   How many HTTP requests to send to a single host per second (default is unlimited).
+    --http-retries <n>      How often to retry HTTP requests that timed out or were rate limited (default is 2).
+    --http-retry-delay <duration>
+                            How long to wait before the first retry, doubling after each one (default is 1s).
+    --deny-transient-errors Fail on HTTP errors that might go away by themselves, instead of only warning.
+    --http-connect-timeout <duration>
+                            How long to wait for an HTTP connection to be established (default is 10s).
+    --http-read-timeout <duration>
+                            How long to wait for each read from an HTTP server (default is 30s).
+    --user-agent <agent>    The User-Agent header to send with HTTP requests.
+    --http-header <header>  Send a header to URLs matching a pattern, as '<pattern> <name>: <value>'. Can be repeated.
+                            '${VAR}' in the value is replaced with the environment variable VAR.
+    --http-proxy <url>      Send all HTTP requests through this proxy.
+    --http-ca-bundle <file> Trust the certificates in this PEM file, in addition to the default ones.
+    --max-redirects <n>     How many redirects to follow before giving up (default is 5).
+    --deny-cross-host-redirects
+                            Give an error for links which redirect to a different host, e.g. a login page.
+    --http-cache <file>     Cache the results of HTTP checks in this file and reuse them in later runs,
+                            e.g. target/deadlinks/http-cache.json.
+    --http-cache-ttl <duration>
+                            How long successful HTTP results are reused, e.g. '12h' or '7d' (default is 1d).
+    --http-cache-failure-ttl <duration>
+                            How long failed HTTP results are reused (default is 1h).
+    --refresh-http-cache    Check all HTTP links again, ignoring the results in the cache.
+    --clear-http-cache      Remove all existing results from the cache.
+    --markdown              Also check the Markdown files in the workspace, e.g. README.md and docs/*.md.
+                            Links to headings are checked using the same anchors as GitHub.
+    --with-deps             Also document dependencies, so that links into their documentation can be checked.
+                            Only errors in the workspace's own crates are reported.
+    --local-docs            Check links to doc.rust-lang.org against the documentation installed by rustup
+                            (`rustup component add rust-docs`), and links to docs.rs against the documentation
+                            of dependencies in the target directory, instead of assuming they are valid.
+                            Use together with --with-deps.
+    --rustdoc-json          Check rustdoc's JSON output instead of HTML. This finds broken intra-doc links precisely,
+                            but requires a nightly toolchain, e.g. `cargo +nightly deadlinks --rustdoc-json`.
     --no-build              Do not call `cargo doc` before running link checking. By default, deadlinks will call `cargo doc` if `--dir` is not passed.
     --debug                 Use debug output. This option is deprecated; use `RUST_LOG=debug` instead.
     -v --verbose            Use verbose output. This option is deprecated; use `RUST_LOG=info` instead.
     -V --version            Print version info and exit.
 
+Flags which turn a setting on or off have an opposite, to override the configuration file:
+--no-check-http, --check-fragments, --no-check-intra-doc-links, --no-orphan-pages, --allow-transient-errors,
+--allow-cross-host-redirects and --no-local-docs.
+
 CARGO_ARGS will be passed verbatim to `cargo doc` (as long as `--no-build` is not passed).
 ";
 
 #[derive(Debug, Deserialize)]
 struct MainArgs {
     arg_directory: Option<String>,
+    arg_book: Option<PathBuf>,
     arg_cargo_directory: Option<OsString>,
+    arg_config: Option<PathBuf>,
     flag_verbose: bool,
     flag_debug: bool,
-    flag_check_http: bool,
-    flag_forbid_http: bool,
-    flag_check_intra_doc_links: bool,
+    arg_http: Option<HttpCheck>,
+    flag_check_intra_doc_links: Option<bool>,
     flag_no_build: bool,
-    flag_ignore_fragments: bool,
+    flag_rustdoc_json: bool,
+    flag_with_deps: bool,
+    flag_markdown: bool,
+    flag_check_fragments: Option<bool>,
+    flag_local_docs: Option<bool>,
+    arg_link_kinds: Option<HashSet<LinkKind>>,
+    arg_format: shared::Format,
+    ignore: shared::IgnoreArgs,
+    site: shared::SiteArgs,
+    orphans: shared::OrphanArgs,
+    baseline: shared::BaselineArgs,
+    fix: shared::FixArgs,
+    http_limits: shared::HttpLimitArgs,
+    http_client: shared::HttpClientArgs,
+    http_cache: shared::HttpCacheArgs,
     cargo_args: Vec<OsString>,
 }
 
-impl From<&MainArgs> for CheckContext {
-    fn from(args: &MainArgs) -> CheckContext {
-        let check_http = if args.flag_check_http {
-            HttpCheck::Enabled
-        } else if args.flag_forbid_http {
-            HttpCheck::Forbidden
-        } else {
-            HttpCheck::Ignored
-        };
-        CheckContext {
-            check_http,
-            verbose: args.flag_debug,
-            check_fragments: !args.flag_ignore_fragments,
+impl From<&MainArgs> for Config {
+    fn from(args: &MainArgs) -> Config {
+        Config {
+            http: args.arg_http,
+            check_fragments: args.flag_check_fragments,
             check_intra_doc_links: args.flag_check_intra_doc_links,
+            link_kinds: args.arg_link_kinds.clone(),
+            http_cache: args.http_cache.arg_http_cache.clone(),
+            http_cache_ttl: args.http_cache.arg_http_cache_ttl,
+            http_cache_failure_ttl: args.http_cache.arg_http_cache_failure_ttl,
+            max_http_requests: args.http_limits.arg_max_http_requests,
+            max_http_requests_per_host: args.http_limits.arg_max_http_requests_per_host,
+            http_rate_limit: args.http_limits.arg_http_rate_limit,
+            http_retries: args.http_limits.arg_http_retries,
+            http_retry_delay: args.http_limits.arg_http_retry_delay,
+            deny_transient_errors: args.http_limits.flag_deny_transient_errors,
+            http_connect_timeout: args.http_client.arg_http_connect_timeout,
+            http_read_timeout: args.http_client.arg_http_read_timeout,
+            user_agent: args.http_client.arg_user_agent.clone(),
+            http_proxy: args.http_client.arg_http_proxy.clone(),
+            http_ca_bundle: args.http_client.arg_http_ca_bundle.clone(),
+            http_headers: args.http_client.arg_http_header.clone(),
+            max_redirects: args.http_client.arg_max_redirects,
+            deny_cross_host_redirects: args.http_client.flag_deny_cross_host_redirects,
+            local_docs: args.flag_local_docs,
+            site_root: args.site.arg_site_root.clone(),
+            base_url: args.site.arg_base_url.clone(),
+            ignore_links: args.ignore.arg_ignore_link.clone(),
+            ignore_files: args.ignore.arg_ignore_file.clone(),
+            orphan_pages: args.orphans.flag_orphan_pages,
+            entry_points: args.orphans.arg_entry_point.clone(),
         }
     }
 }
@@ -69,7 +169,7 @@ fn parse_args() -> Result<MainArgs, shared::PicoError> {
 
     let mut args: Vec<_> = std::env::args_os().collect();
     args.remove(0);
-    if args.get(0).map_or(true, |arg| arg != "deadlinks") {
+    if !matches!(args.first(), Some(arg) if arg == "deadlinks") {
         return Err(Error::ArgumentParsingFailed {
             cause: "cargo-deadlinks should be run as `cargo deadlinks`".into(),
         }
@@ -95,26 +195,46 @@ fn parse_args() -> Result<MainArgs, shared::PicoError> {
     }
     let main_args = MainArgs {
         arg_directory: args.opt_value_from_str("--dir")?,
+        arg_book: args
+            .opt_value_from_os_str("--book", |s| Result::<_, Error>::Ok(PathBuf::from(s)))?,
         arg_cargo_directory: args
             .opt_value_from_os_str("--cargo-dir", |s| Result::<_, Error>::Ok(s.to_owned()))?,
+        arg_config: args
+            .opt_value_from_os_str("--config", |s| Result::<_, Error>::Ok(PathBuf::from(s)))?,
         flag_verbose: args.contains(["-v", "--verbose"]),
         flag_debug: args.contains("--debug"),
         flag_no_build: args.contains("--no-build"),
-        flag_ignore_fragments: args.contains("--ignore-fragments"),
-        flag_check_intra_doc_links: args.contains("--check-intra-doc-links"),
-        flag_check_http: args.contains("--check-http"),
-        flag_forbid_http: args.contains("--forbid-http"),
+        flag_rustdoc_json: args.contains("--rustdoc-json"),
+        flag_with_deps: args.contains("--with-deps"),
+        flag_markdown: args.contains("--markdown"),
+        flag_check_fragments: shared::parse_toggle(
+            &mut args,
+            "--check-fragments",
+            "--ignore-fragments",
+        )?,
+        flag_local_docs: shared::parse_toggle(&mut args, "--local-docs", "--no-local-docs")?,
+        flag_check_intra_doc_links: shared::parse_toggle(
+            &mut args,
+            "--check-intra-doc-links",
+            "--no-check-intra-doc-links",
+        )?,
+        arg_http: shared::parse_http_check(&mut args)?,
+        arg_link_kinds: args.opt_value_from_fn("--link-kinds", shared::parse_link_kinds)?,
+        arg_format: args
+            .opt_value_from_str("--format")?
+            .unwrap_or(shared::Format::Human),
+        ignore: shared::IgnoreArgs::parse(&mut args)?,
+        site: shared::SiteArgs::parse(&mut args)?,
+        orphans: shared::OrphanArgs::parse(&mut args)?,
+        baseline: shared::BaselineArgs::parse(&mut args)?,
+        fix: shared::FixArgs::parse(&mut args)?,
+        http_limits: shared::HttpLimitArgs::parse(&mut args)?,
+        http_client: shared::HttpClientArgs::parse(&mut args)?,
+        http_cache: shared::HttpCacheArgs::parse(&mut args)?,
         cargo_args,
     };
     args.finish()?;
-    if main_args.flag_forbid_http && main_args.flag_check_http {
-        Err(pico_args::Error::ArgumentParsingFailed {
-            cause: "--check-http and --forbid-http are mutually incompatible".into(),
-        }
-        .into())
-    } else {
-        Ok(main_args)
-    }
+    Ok(main_args)
 }
 
 fn main() {
@@ -128,24 +248,96 @@ fn main() {
 
     shared::init_logger(args.flag_debug, args.flag_verbose, "cargo_deadlinks");
 
-    let dirs = args.arg_directory.as_ref().map_or_else(
-        || {
-            let dir = args.arg_cargo_directory.as_deref();
-            determine_dir(args.flag_no_build, &args.cargo_args, dir)
-        },
-        |dir| vec![dir.into()],
-    );
+    let cargo_dir = args.arg_cargo_directory.as_deref();
+    // Only used for configuration and `--no-build`, so don't give an error yet if this fails.
+    let metadata = {
+        let mut command = MetadataCommand::new();
+        command.no_deps();
+        if let Some(dir) = cargo_dir {
+            command.current_dir(dir);
+        }
+        command.exec()
+    };
+
+    // Settings are read from (in increasing order of priority) `[workspace.metadata.deadlinks]`,
+    // `deadlinks.toml` at the root of the workspace, `[package.metadata.deadlinks]`, and the command line.
+    let workspace_config = match &metadata {
+        Ok(metadata) => {
+            let root = metadata.workspace_root.as_std_path();
+            let cargo_config = Config::from_cargo_metadata(&metadata.workspace_metadata, root);
+            shared::unwrap_config(cargo_config)
+                .merge(shared::read_config_file(args.arg_config.as_deref(), root))
+        }
+        Err(_) => {
+            let dir = Path::new(cargo_dir.unwrap_or_else(|| OsStr::new(".")));
+            shared::read_config_file(args.arg_config.as_deref(), dir)
+        }
+    };
+    // Links are fixed in every package of the workspace.
+    let source_root = match &metadata {
+        Ok(metadata) => metadata.workspace_root.clone().into_std_path_buf(),
+        Err(_) => PathBuf::from(cargo_dir.unwrap_or_else(|| OsStr::new("."))),
+    };
+    // Span filenames in rustdoc JSON are relative to the workspace root; show them relative to the current directory.
+    let json_source_root = env::current_dir()
+        .ok()
+        .and_then(|cwd| source_root.strip_prefix(cwd).ok())
+        .unwrap_or(&source_root)
+        .to_owned();
+    let source_roots = match &metadata {
+        Ok(metadata) => source_roots(metadata),
+        Err(_) => HashMap::new(),
+    };
+    let cli_config = Config::from(&args);
+    let global_config = workspace_config.clone().merge(cli_config.clone());
+    let http_cache = shared::load_http_cache(&global_config, &args.http_cache);
 
-    let ctx = CheckContext::from(&args);
+    let dirs = if args.arg_book.is_some() {
+        Vec::new()
+    } else {
+        args.arg_directory.as_ref().map_or_else(
+            || {
+                determine_dir(
+                    args.flag_no_build,
+                    args.flag_rustdoc_json,
+                    args.flag_with_deps,
+                    &args.cargo_args,
+                    cargo_dir,
+                    &metadata,
+                )
+            },
+            |dir| vec![(dir.into(), None)],
+        )
+    };
+
+    let mut report = Report::new();
+    let mut ignored = shared::IgnoredCounts::default();
     let mut errors = false;
-    for dir in &dirs {
+    for (dir, package_id) in &dirs {
+        let package_config = match (&metadata, package_id) {
+            (Ok(metadata), Some(id)) => metadata
+                .packages
+                .iter()
+                .find(|package| package.id == *id)
+                .map(|package| {
+                    let manifest_dir = package.manifest_path.parent().unwrap().as_std_path();
+                    let config = Config::from_cargo_metadata(&package.metadata, manifest_dir);
+                    shared::unwrap_config(config)
+                })
+                .unwrap_or_default(),
+            _ => Config::default(),
+        };
+        let config = workspace_config
+            .clone()
+            .merge(package_config)
+            .merge(cli_config.clone());
         let dir = match dir.canonicalize() {
             Ok(dir) => dir,
             Err(_) => {
                 eprintln!("error: could not find directory {:?}.", dir);
                 if args.arg_directory.is_none() {
                     assert!(
-                        args.flag_no_build,
+                        args.flag_no_build || args.flag_rustdoc_json,
                         "cargo said it built a directory it didn't build"
                     );
                     eprintln!(
@@ -155,54 +347,225 @@ fn main() {
                 process::exit(1);
             }
         };
+        let mut ctx = CheckContext {
+            verbose: args.flag_debug,
+            http_cache: http_cache.clone(),
+            source_roots: source_roots.clone(),
+            std_docs: shared::std_docs(&config),
+            // Every crate is documented in `target/doc`, so that is the root of the site.
+            site_dir: if args.arg_directory.is_none() {
+                dir.parent().map(Path::to_owned)
+            } else {
+                None
+            },
+            // Dependencies are documented next to the crates themselves.
+            dependency_docs: if config.local_docs == Some(true) {
+                dir.parent().map(Path::to_owned)
+            } else {
+                None
+            },
+            ..CheckContext::default()
+        };
+        config.apply(&mut ctx);
+        shared::validate_http_client(&ctx);
+
         log::info!("checking directory {:?}", dir);
-        if walk_dir(&dir, &ctx) {
-            errors = true;
+        let print_now = args.arg_format == shared::Format::Human
+            && !args.baseline.is_active()
+            && !args.fix.is_active();
+        if shared::is_rustdoc_json(&dir) {
+            let files = shared::check_rustdoc_json(&dir, &json_source_root, &ctx);
+            if print_now {
+                for file in &files {
+                    println!("{}", file);
+                    errors |= file.has_errors();
+                }
+            } else {
+                report.add_dir(dir.parent().unwrap(), files);
+            }
+        } else if print_now {
+            if walk_dir(&dir, &ctx) {
+                errors = true;
+            }
+        } else {
+            report.add_dir(&dir, check_dir(&dir, &ctx));
         }
+        ignored.add(&ctx);
     }
+    // Outside of the documentation itself, links to docs.rs are checked against `cargo doc`'s output, if it exists.
+    let target_doc_dir = match &metadata {
+        Ok(metadata) => Some(metadata.target_directory.join("doc").into_std_path_buf())
+            .filter(|dir| dir.is_dir()),
+        Err(_) => None,
+    };
+    if let Some(dir) = &args.arg_book {
+        let book = load_book(dir, args.flag_no_build);
+        let config = workspace_config
+            .clone()
+            .merge(book.config.clone().unwrap_or_default())
+            .merge(cli_config.clone());
+        let mut ctx = CheckContext {
+            verbose: args.flag_debug,
+            http_cache: http_cache.clone(),
+            std_docs: shared::std_docs(&config),
+            dependency_docs: target_doc_dir
+                .clone()
+                .filter(|_| config.local_docs == Some(true)),
+            ..CheckContext::default()
+        };
+        config.apply(&mut ctx);
+        shared::validate_http_client(&ctx);
+        log::info!("checking book in {:?}", book.html_dir);
+        let files = book.check_output(&ctx);
+        if args.arg_format == shared::Format::Human
+            && !args.baseline.is_active()
+            && !args.fix.is_active()
+        {
+            for file in &files {
+                println!("{}", file);
+                errors |= file.has_errors();
+            }
+        } else {
+            report.add_dir(&book.html_dir, files);
+        }
+        ignored.add(&ctx);
+    }
+    if args.flag_markdown {
+        let mut ctx = CheckContext {
+            verbose: args.flag_debug,
+            http_cache: http_cache.clone(),
+            std_docs: shared::std_docs(&global_config),
+            dependency_docs: target_doc_dir.filter(|_| global_config.local_docs == Some(true)),
+            ..CheckContext::default()
+        };
+        global_config.apply(&mut ctx);
+        shared::validate_http_client(&ctx);
+        let root = source_root.canonicalize().unwrap_or_else(|_| {
+            eprintln!("error: could not find directory {:?}.", source_root);
+            process::exit(1);
+        });
+        log::info!("checking Markdown files in {:?}", root);
+        let files = check_markdown(&root, &ctx);
+        if args.arg_format == shared::Format::Human
+            && !args.baseline.is_active()
+            && !args.fix.is_active()
+        {
+            for file in &files {
+                println!("{}", file);
+                errors |= file.has_errors();
+            }
+        } else {
+            report.add_dir(&root, files);
+        }
+        ignored.add(&ctx);
+    }
+    ignored.print();
+    if args.arg_format != shared::Format::Human || args.baseline.is_active() || args.fix.is_active()
+    {
+        args.fix.apply(&report, &source_root);
+        args.baseline.apply(&mut report);
+        shared::print_report(args.arg_format, &report);
+        errors = report.has_errors();
+    }
+    shared::save_http_cache(http_cache.as_deref());
     if errors {
         process::exit(1);
-    } else if dirs.is_empty() {
+    } else if dirs.is_empty() && args.arg_book.is_none() {
         assert!(args.arg_directory.is_none());
         eprintln!("warning: no directories were detected");
     }
 }
 
+/// Reads the `book.toml` in `dir` and builds the book with `mdbook build`, unless `no_build` is passed.
+///
+/// Exits the process if the book can't be read or built.
+fn load_book(dir: &Path, no_build: bool) -> Book {
+    let root = dir.canonicalize().unwrap_or_else(|_| {
+        eprintln!("error: could not find directory {:?}.", dir);
+        process::exit(1);
+    });
+    let book = Book::load(&root).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    });
+    if !no_build {
+        log::info!("building book using mdbook");
+        let status = Command::new("mdbook").arg("build").arg(&root).status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => {
+                eprintln!("error: `mdbook build` failed");
+                process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("error: could not run `mdbook build`: {}", err);
+                eprintln!("help: install mdBook with `cargo install mdbook`, or pass `--no-build` to check the existing output.");
+                process::exit(1);
+            }
+        }
+    }
+    if !book.html_dir.is_dir() {
+        eprintln!(
+            "error: could not find the built book in {:?}.",
+            book.html_dir
+        );
+        eprintln!("help: consider removing `--no-build`, or running `mdbook build` yourself.");
+        process::exit(1);
+    }
+    book
+}
+
+/// The directory containing the root module of each documented crate in the workspace, by crate name.
+///
+/// Directories are relative to the current directory if possible, to keep the output short.
+fn source_roots(metadata: &Metadata) -> HashMap<String, PathBuf> {
+    let cwd = env::current_dir().ok();
+    let mut roots = HashMap::new();
+    for target in metadata
+        .packages
+        .iter()
+        .flat_map(|package| &package.targets)
+        .filter(|target| has_docs(target))
+    {
+        let dir = match target.src_path.parent() {
+            Some(dir) => dir.as_std_path(),
+            None => continue,
+        };
+        let dir = cwd
+            .as_ref()
+            .and_then(|cwd| dir.strip_prefix(cwd).ok())
+            .unwrap_or(dir);
+        // Libraries come first, and are documented instead of binaries with the same name.
+        roots
+            .entry(target.name.replace('-', "_"))
+            .or_insert_with(|| dir.to_owned());
+    }
+    roots
+}
+
 /// Returns the directories to use as root of the documentation.
 ///
 /// If an directory has been provided as CLI argument that one is used.
 /// Otherwise, if `no_build` is passed, we try to find the `Cargo.toml` and
 /// construct the documentation path from the package name found there.
 /// Otherwise, build the documentation and have cargo itself tell us where it is.
+/// If `rustdoc_json` is passed, the documentation is built as rustdoc JSON and the JSON files are returned instead.
+/// If `with_deps` is passed, dependencies are documented as well, but only the directories of workspace members are returned.
+///
+/// Each directory is returned along with the package it documents, if known.
 ///
 /// All *.html files under the root directory will be checked.
 fn determine_dir(
     no_build: bool,
+    rustdoc_json: bool,
+    with_deps: bool,
     cargo_args: &[OsString],
     cargo_dir: Option<&OsStr>,
-) -> Vec<cargo_metadata::camino::Utf8PathBuf> {
+    metadata: &Result<Metadata, cargo_metadata::Error>,
+) -> Vec<(Utf8PathBuf, Option<PackageId>)> {
     if no_build {
         eprintln!("warning: --no-build ignores `doc = false` and may have other bugs");
-        let manifest = MetadataCommand::new()
-            .no_deps()
-            .exec()
-            .unwrap_or_else(|err| {
-                println!("error: {}", err);
-                println!("help: if this is not a cargo directory, use `--dir`");
-                process::exit(1);
-            });
-        let doc = manifest.target_directory.join("doc");
-
-        // originally written with this impressively bad jq query:
-        // `.packages[] |select(.source == null) | .targets[] | select(.kind[] | contains("test") | not) | .name`
-        let iter = manifest
-            .packages
-            .into_iter()
-            .filter(|package| package.source.is_none())
-            .flat_map(|package| package.targets)
-            .filter(has_docs)
-            .map(move |target| doc.join(target.name.replace('-', "_")));
-        return iter.collect();
+        return local_doc_dirs(metadata, rustdoc_json);
     }
 
     // Build the documentation, collecting info about the build at the same time.
@@ -213,19 +576,23 @@ fn determine_dir(
     });
     // Stolen from https://docs.rs/cargo_metadata/0.12.0/cargo_metadata/#examples
     let mut cargo_process = Command::new(cargo);
-    #[allow(clippy::needless_borrow)] // MSRV is 1.46
+    cargo_process.arg("doc");
+    if !with_deps {
+        cargo_process.arg("--no-deps");
+    }
+    #[allow(clippy::needless_borrow, clippy::needless_borrows_for_generic_args)] // MSRV is 1.46
     cargo_process
-        .args(&[
-            "doc",
-            "--no-deps",
-            "--message-format",
-            "json-render-diagnostics",
-        ])
+        .args(&["--message-format", "json-render-diagnostics"])
         .args(cargo_args)
         .stdout(process::Stdio::piped());
     if let Some(dir) = cargo_dir {
         cargo_process.current_dir(dir);
     }
+    if rustdoc_json {
+        let mut flags = env::var("RUSTDOCFLAGS").unwrap_or_default();
+        flags.push_str(" -Z unstable-options --output-format json");
+        cargo_process.env("RUSTDOCFLAGS", flags.trim_start());
+    }
     // spawn instead of output() allows running deadlinks and cargo in parallel;
     // this is helpful when you have many dependencies that take a while to document
     let mut cargo_process = cargo_process.spawn().unwrap();
@@ -234,14 +601,25 @@ fn determine_dir(
     // `select(.reason == "compiler-artifact") | .filenames[] | select(endswith("/index.html")) | rtrimstr("/index.html")`
     let directories = Message::parse_stream(reader)
         .filter_map(|message| match message {
-            Ok(Message::CompilerArtifact(artifact)) => Some(artifact.filenames),
+            Ok(Message::CompilerArtifact(artifact)) => Some(artifact),
             _ => None,
         })
-        .flatten()
-        .filter(|path| path.file_name() == Some("index.html"))
-        .map(|mut path| {
+        // Dependencies are only documented so that links into their documentation resolve.
+        .filter(|artifact| match metadata {
+            Ok(metadata) if with_deps => metadata.workspace_members.contains(&artifact.package_id),
+            _ => true,
+        })
+        .flat_map(|artifact| {
+            let package_id = artifact.package_id;
+            artifact
+                .filenames
+                .into_iter()
+                .map(move |path| (path, Some(package_id.clone())))
+        })
+        .filter(|(path, _)| path.file_name() == Some("index.html"))
+        .map(|(mut path, package_id)| {
             path.pop();
-            path
+            (path, package_id)
         })
         // TODO: run this in parallel, which should speed up builds a fair bit.
         // This will be hard because either cargo's progress bar will overlap with our output,
@@ -250,12 +628,56 @@ fn determine_dir(
         .collect();
     let status = cargo_process.wait().unwrap();
     if !status.success() {
+        if rustdoc_json {
+            eprintln!("help: rustdoc JSON requires a nightly toolchain, e.g. `cargo +nightly deadlinks --rustdoc-json`");
+        }
         eprintln!("help: if this is not a cargo directory, use `--dir`");
         process::exit(status.code().unwrap_or(2));
     }
+    if rustdoc_json {
+        // cargo reports the HTML it would have generated, not the JSON files.
+        // Targets which were not documented, e.g. because of `doc = false`, don't have one.
+        return local_doc_dirs(metadata, true)
+            .into_iter()
+            .filter(|(path, _)| path.exists())
+            .collect();
+    }
     directories
 }
 
+/// The documentation directories (or rustdoc JSON files) of all packages in the workspace,
+/// based on the names of their targets.
+fn local_doc_dirs(
+    metadata: &Result<Metadata, cargo_metadata::Error>,
+    rustdoc_json: bool,
+) -> Vec<(Utf8PathBuf, Option<PackageId>)> {
+    let manifest = metadata.as_ref().unwrap_or_else(|err| {
+        println!("error: {}", err);
+        println!("help: if this is not a cargo directory, use `--dir`");
+        process::exit(1);
+    });
+    let doc = manifest.target_directory.join("doc");
+
+    // originally written with this impressively bad jq query:
+    // `.packages[] |select(.source == null) | .targets[] | select(.kind[] | contains("test") | not) | .name`
+    let iter = manifest
+        .packages
+        .iter()
+        .filter(|package| package.source.is_none())
+        .flat_map(|package| package.targets.iter().map(move |target| (package, target)))
+        .filter(|(_, target)| has_docs(target))
+        .map(move |(package, target)| {
+            let name = target.name.replace('-', "_");
+            let dir = if rustdoc_json {
+                doc.join(format!("{}.json", name))
+            } else {
+                doc.join(name)
+            };
+            (dir, Some(package.id.clone()))
+        });
+    iter.collect()
+}
+
 fn has_docs(target: &cargo_metadata::Target) -> bool {
     // Ignore tests, examples, and benchmarks, but still document binaries
 
@@ -1,7 +1,10 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::process;
 
-use cargo_deadlinks::{walk_dir, CheckContext, HttpCheck};
+use cargo_deadlinks::{
+    check_dir, check_markdown, walk_dir, CheckContext, Config, HttpCheck, LinkKind, Report,
+};
 use serde_derive::Deserialize;
 
 mod shared;
@@ -12,40 +15,126 @@ Check your package's documentation for dead links.
 Usage:
     deadlinks [options] <directory>...
 
+Each <directory> contains HTML documentation, or is a rustdoc JSON file ending in '.json',
+or a Markdown file ending in '.md'.
+Links in rustdoc JSON are attributed to Rust source files relative to the current directory.
+
 Options:
     -h --help               Print this message
+    --config <file>         Read settings from this file (default is deadlinks.toml in the current directory, if it exists).
     --check-http            Check 'http' and 'https' scheme links
     --forbid-http           Give an error if HTTP links are found. This is incompatible with --check-http.
     --ignore-fragments      Don't check URL fragments.
+    --markdown              Check the Markdown files (*.md) in each <directory> instead of HTML.
+    --local-docs            Check links to doc.rust-lang.org against the documentation installed by rustup
+                            (`rustup component add rust-docs`), and links to docs.rs against the other crates
+                            documented in the same <directory>, instead of assuming they are valid.
+    --link-kinds <kinds>    Only check these kinds of links, separated by commas (default is all of them).
+                            Possible kinds: hyperlink, media, resource, script, embed, form.
+    --ignore-link <pattern> Don't check links matching this glob, or regex if it starts with 'regex:'. Can be repeated.
+                            Local files are matched relative to the documentation root, other links by their URL.
+    --ignore-file <pattern> Don't check HTML or Markdown files or directories matching this pattern. Can be repeated.
+    --base-url <url>        The URL each <directory> is deployed at, e.g. 'https://example.com/docs/'. Links to
+                            the same site are checked against the local files instead of over HTTP.
+    --site-root <path>      The path each <directory> is served under, e.g. '/docs/' (default is the path of
+                            --base-url, or '/'). Absolute paths are resolved relative to it; other absolute paths are skipped.
+    --orphan-pages          Give an error for pages which can't be reached by following links from the index.html of
+                            each <directory>, e.g. stale files left over from previous builds.
+    --entry-point <path>    With --orphan-pages, also follow links from this page. Can be repeated.
+    --format <format>       How to print errors: 'human' (the default), 'json' or 'sarif'.
+    --baseline <file>       Only fail on errors which are not in this baseline, and list the ones that were fixed.
+    --write-baseline <file> Record all errors in a baseline file, to be passed to `--baseline` later.
+    --fix                   Rewrite links in doc comments which permanently redirect, or point to items which were moved.
+    --dry-run               With --fix, print the changes as a diff instead of writing them.
+    --max-http-requests <n> How many HTTP requests to send at once (default is 16).
+    --max-http-requests-per-host <n>
+                            How many HTTP requests to send to a single host at once (default is 4).
+    --http-rate-limit <n>   How many HTTP requests to send to a single host per second (default is unlimited).
+    --http-retries <n>      How often to retry HTTP requests that timed out or were rate limited (default is 2).
+    --http-retry-delay <duration>
+                            How long to wait before the first retry, doubling after each one (default is 1s).
+    --deny-transient-errors Fail on HTTP errors that might go away by themselves, instead of only warning.
+    --http-connect-timeout <duration>
+                            How long to wait for an HTTP connection to be established (default is 10s).
+    --http-read-timeout <duration>
+                            How long to wait for each read from an HTTP server (default is 30s).
+    --user-agent <agent>    The User-Agent header to send with HTTP requests.
+    --http-header <header>  Send a header to URLs matching a pattern, as '<pattern> <name>: <value>'. Can be repeated.
+                            '${VAR}' in the value is replaced with the environment variable VAR.
+    --http-proxy <url>      Send all HTTP requests through this proxy.
+    --http-ca-bundle <file> Trust the certificates in this PEM file, in addition to the default ones.
+    --max-redirects <n>     How many redirects to follow before giving up (default is 5).
+    --deny-cross-host-redirects
+                            Give an error for links which redirect to a different host, e.g. a login page.
+    --http-cache <file>     Cache the results of HTTP checks in this file and reuse them in later runs.
+    --http-cache-ttl <duration>
+                            How long successful HTTP results are reused, e.g. '12h' or '7d' (default is 1d).
+    --http-cache-failure-ttl <duration>
+                            How long failed HTTP results are reused (default is 1h).
+    --refresh-http-cache    Check all HTTP links again, ignoring the results in the cache.
+    --clear-http-cache      Remove all existing results from the cache.
     --debug                 Use debug output
     -v --verbose            Use verbose output
     -V --version            Print version info and exit.
+
+Flags which turn a setting on or off have an opposite, to override the configuration file:
+--no-check-http, --check-fragments, --no-orphan-pages, --allow-transient-errors,
+--allow-cross-host-redirects and --no-local-docs.
 ";
 
 #[derive(Debug, Deserialize)]
 struct MainArgs {
     arg_directory: Vec<PathBuf>,
+    arg_config: Option<PathBuf>,
     flag_verbose: bool,
     flag_debug: bool,
-    flag_check_http: bool,
-    flag_forbid_http: bool,
-    flag_ignore_fragments: bool,
+    arg_http: Option<HttpCheck>,
+    flag_check_fragments: Option<bool>,
+    flag_local_docs: Option<bool>,
+    flag_markdown: bool,
+    arg_link_kinds: Option<HashSet<LinkKind>>,
+    arg_format: shared::Format,
+    ignore: shared::IgnoreArgs,
+    site: shared::SiteArgs,
+    orphans: shared::OrphanArgs,
+    baseline: shared::BaselineArgs,
+    fix: shared::FixArgs,
+    http_limits: shared::HttpLimitArgs,
+    http_client: shared::HttpClientArgs,
+    http_cache: shared::HttpCacheArgs,
 }
 
-impl From<&MainArgs> for CheckContext {
-    fn from(args: &MainArgs) -> CheckContext {
-        let check_http = if args.flag_check_http {
-            HttpCheck::Enabled
-        } else if args.flag_forbid_http {
-            HttpCheck::Forbidden
-        } else {
-            HttpCheck::Ignored
-        };
-        CheckContext {
-            check_http,
-            verbose: args.flag_debug,
-            check_fragments: !args.flag_ignore_fragments,
-            check_intra_doc_links: false,
+impl From<&MainArgs> for Config {
+    fn from(args: &MainArgs) -> Config {
+        Config {
+            http: args.arg_http,
+            check_fragments: args.flag_check_fragments,
+            check_intra_doc_links: None,
+            link_kinds: args.arg_link_kinds.clone(),
+            http_cache: args.http_cache.arg_http_cache.clone(),
+            http_cache_ttl: args.http_cache.arg_http_cache_ttl,
+            http_cache_failure_ttl: args.http_cache.arg_http_cache_failure_ttl,
+            max_http_requests: args.http_limits.arg_max_http_requests,
+            max_http_requests_per_host: args.http_limits.arg_max_http_requests_per_host,
+            http_rate_limit: args.http_limits.arg_http_rate_limit,
+            http_retries: args.http_limits.arg_http_retries,
+            http_retry_delay: args.http_limits.arg_http_retry_delay,
+            deny_transient_errors: args.http_limits.flag_deny_transient_errors,
+            http_connect_timeout: args.http_client.arg_http_connect_timeout,
+            http_read_timeout: args.http_client.arg_http_read_timeout,
+            user_agent: args.http_client.arg_user_agent.clone(),
+            http_proxy: args.http_client.arg_http_proxy.clone(),
+            http_ca_bundle: args.http_client.arg_http_ca_bundle.clone(),
+            http_headers: args.http_client.arg_http_header.clone(),
+            max_redirects: args.http_client.arg_max_redirects,
+            deny_cross_host_redirects: args.http_client.flag_deny_cross_host_redirects,
+            local_docs: args.flag_local_docs,
+            site_root: args.site.arg_site_root.clone(),
+            base_url: args.site.arg_base_url.clone(),
+            ignore_links: args.ignore.arg_ignore_link.clone(),
+            ignore_files: args.ignore.arg_ignore_file.clone(),
+            orphan_pages: args.orphans.flag_orphan_pages,
+            entry_points: args.orphans.arg_entry_point.clone(),
         }
     }
 }
@@ -60,20 +149,48 @@ fn parse_args() -> Result<MainArgs, shared::PicoError> {
         std::process::exit(0);
     }
     let args = MainArgs {
+        arg_config: args.opt_value_from_os_str("--config", |s| {
+            Result::<_, pico_args::Error>::Ok(PathBuf::from(s))
+        })?,
         flag_verbose: args.contains(["-v", "--verbose"]),
         flag_debug: args.contains("--debug"),
-        flag_ignore_fragments: args.contains("--ignore-fragments"),
-        flag_check_http: args.contains("--check-http"),
-        flag_forbid_http: args.contains("--forbid-http"),
+        flag_check_fragments: shared::parse_toggle(
+            &mut args,
+            "--check-fragments",
+            "--ignore-fragments",
+        )?,
+        flag_local_docs: shared::parse_toggle(&mut args, "--local-docs", "--no-local-docs")?,
+        flag_markdown: args.contains("--markdown"),
+        arg_http: shared::parse_http_check(&mut args)?,
+        arg_link_kinds: args.opt_value_from_fn("--link-kinds", shared::parse_link_kinds)?,
+        arg_format: args
+            .opt_value_from_str("--format")?
+            .unwrap_or(shared::Format::Human),
+        ignore: shared::IgnoreArgs::parse(&mut args)?,
+        site: shared::SiteArgs::parse(&mut args)?,
+        orphans: shared::OrphanArgs::parse(&mut args)?,
+        baseline: shared::BaselineArgs::parse(&mut args)?,
+        fix: shared::FixArgs::parse(&mut args)?,
+        http_limits: shared::HttpLimitArgs::parse(&mut args)?,
+        http_client: shared::HttpClientArgs::parse(&mut args)?,
+        http_cache: shared::HttpCacheArgs::parse(&mut args)?,
         arg_directory: args.free_os()?.into_iter().map(Into::into).collect(),
     };
-    if args.flag_forbid_http && args.flag_check_http {
-        Err(pico_args::Error::ArgumentParsingFailed {
-            cause: "--check-http and --forbid-http are mutually incompatible".into(),
-        }
-        .into())
+    Ok(args)
+}
+
+/// Whether `path` is a Markdown file rather than a directory of HTML files.
+fn is_markdown(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == "md" || ext == "markdown")
+}
+
+/// The directory rustdoc documented all crates in, since dependencies are documented next to the crates themselves:
+/// `dir` itself if it is e.g. `target/doc`, or its parent if it is e.g. `target/doc/my_crate`.
+fn doc_root(dir: &Path) -> &Path {
+    if dir.join("crates.js").is_file() || dir.join("settings.html").is_file() {
+        dir
     } else {
-        Ok(args)
+        dir.parent().unwrap_or(dir)
     }
 }
 
@@ -92,7 +209,17 @@ fn main() {
     shared::init_logger(args.flag_debug, args.flag_verbose, "deadlinks");
 
     let mut errors = false;
-    let ctx = CheckContext::from(&args);
+    let mut report = Report::new();
+    let config = shared::read_config_file(args.arg_config.as_deref(), Path::new("."))
+        .merge(Config::from(&args));
+    let mut ctx = CheckContext {
+        verbose: args.flag_debug,
+        http_cache: shared::load_http_cache(&config, &args.http_cache),
+        std_docs: shared::std_docs(&config),
+        ..CheckContext::default()
+    };
+    config.apply(&mut ctx);
+    shared::validate_http_client(&ctx);
     for relative_dir in args.arg_directory {
         let dir = match relative_dir.canonicalize() {
             Ok(dir) => dir,
@@ -102,8 +229,55 @@ fn main() {
             }
         };
         log::info!("checking directory {:?}", dir);
-        errors |= walk_dir(&dir, &ctx);
+        let print_now = args.arg_format == shared::Format::Human
+            && !args.baseline.is_active()
+            && !args.fix.is_active();
+        if shared::is_rustdoc_json(&dir) {
+            let files = shared::check_rustdoc_json(&dir, Path::new(""), &ctx);
+            if print_now {
+                for file in &files {
+                    println!("{}", file);
+                    errors |= file.has_errors();
+                }
+            } else {
+                report.add_dir(dir.parent().unwrap(), files);
+            }
+        } else if args.flag_markdown || is_markdown(&dir) {
+            let files = check_markdown(&dir, &ctx);
+            if print_now {
+                for file in &files {
+                    println!("{}", file);
+                    errors |= file.has_errors();
+                }
+            } else if dir.is_dir() {
+                report.add_dir(&dir, files);
+            } else {
+                report.add_dir(dir.parent().unwrap(), files);
+            }
+        } else {
+            let ctx = CheckContext {
+                dependency_docs: Some(doc_root(&dir).to_owned())
+                    .filter(|_| config.local_docs == Some(true)),
+                ..ctx.clone()
+            };
+            if print_now {
+                errors |= walk_dir(&dir, &ctx);
+            } else {
+                report.add_dir(&dir, check_dir(&dir, &ctx));
+            }
+        }
+    }
+    let mut ignored = shared::IgnoredCounts::default();
+    ignored.add(&ctx);
+    ignored.print();
+    if args.arg_format != shared::Format::Human || args.baseline.is_active() || args.fix.is_active()
+    {
+        args.fix.apply(&report, Path::new("."));
+        args.baseline.apply(&mut report);
+        shared::print_report(args.arg_format, &report);
+        errors = report.has_errors();
     }
+    shared::save_http_cache(ctx.http_cache.as_deref());
     if errors {
         process::exit(1);
     }
@@ -0,0 +1,94 @@
+use std::io::{self, Read};
+use std::process;
+
+use cargo_deadlinks::{Book, CheckContext, Config};
+
+// Most of the arguments shared by the other binaries don't apply to a renderer.
+#[allow(dead_code)]
+mod shared;
+
+const MAIN_USAGE: &str = "
+Check the Markdown source of an mdBook for dead links.
+
+Usage:
+    mdbook-deadlinks
+
+This is an mdBook renderer: instead of running it directly, add it to your book.toml,
+and `mdbook build` will fail if any links are broken.
+
+    [output.html]
+
+    [output.deadlinks]
+    # The same settings as in deadlinks.toml, e.g.
+    http = \"enabled\"
+
+Options can be passed with `command = \"mdbook-deadlinks --refresh-http-cache\"` in the same table.
+
+Options:
+    -h --help               Print this message
+    --http-cache <file>     Cache the results of HTTP checks in this file and reuse them in later runs.
+    --http-cache-ttl <duration>
+                            How long successful HTTP results are reused, e.g. '12h' or '7d' (default is 1d).
+    --http-cache-failure-ttl <duration>
+                            How long failed HTTP results are reused (default is 1h).
+    --refresh-http-cache    Check all HTTP links again, ignoring the results in the cache.
+    --clear-http-cache      Remove all existing results from the cache.
+    -V --version            Print version info and exit.
+";
+
+fn main() {
+    let mut args = pico_args::Arguments::from_env();
+    if args.contains(["-V", "--version"]) {
+        println!(concat!("mdbook-deadlinks ", env!("CARGO_PKG_VERSION")));
+        process::exit(0);
+    } else if args.contains(["-h", "--help"]) {
+        println!("{}", MAIN_USAGE);
+        process::exit(0);
+    }
+    let cache_args = match shared::HttpCacheArgs::parse(&mut args) {
+        Ok(cache_args) => cache_args,
+        Err(err) => {
+            eprintln!("error: {}", shared::PicoError(err));
+            process::exit(1);
+        }
+    };
+    env_logger::init();
+
+    let mut context = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut context) {
+        eprintln!(
+            "error: could not read the render context from mdBook: {}",
+            err
+        );
+        process::exit(1);
+    }
+    let book = Book::from_render_context(&context).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        eprintln!("help: mdbook-deadlinks should be run by `mdbook build`; see `mdbook-deadlinks --help`.");
+        process::exit(1);
+    });
+
+    let config = book.config.clone().unwrap_or_default().merge(Config {
+        http_cache: cache_args.arg_http_cache.clone(),
+        http_cache_ttl: cache_args.arg_http_cache_ttl,
+        http_cache_failure_ttl: cache_args.arg_http_cache_failure_ttl,
+        ..Config::default()
+    });
+    let mut ctx = CheckContext {
+        http_cache: shared::load_http_cache(&config, &cache_args),
+        std_docs: shared::std_docs(&config),
+        ..CheckContext::default()
+    };
+    config.apply(&mut ctx);
+    shared::validate_http_client(&ctx);
+
+    log::info!("checking book sources in {:?}", book.src_dir);
+    let files = book.check_sources(&ctx);
+    for file in &files {
+        println!("{}", file);
+    }
+    shared::save_http_cache(ctx.http_cache.as_deref());
+    if files.iter().any(|file| file.has_errors()) {
+        process::exit(1);
+    }
+}
@@ -1,6 +1,19 @@
+use cargo_deadlinks::{
+    Baseline, CheckContext, Config, ConfigError, FileError, HttpCache, HttpCheck, HttpHeader,
+    IgnoreList, IgnorePattern, LinkKind, Report,
+};
 use log::LevelFilter;
-use pico_args::Error;
+use once_cell::sync::OnceCell;
+use pico_args::{Arguments, Error};
+use serde_derive::Deserialize;
+use std::collections::HashSet;
 use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
 
 /// Initalizes the logger according to the provided config flags.
 pub fn init_logger(debug: bool, verbose: bool, krate: &str) {
@@ -19,6 +32,563 @@ pub fn init_logger(debug: bool, verbose: bool, krate: &str) {
     builder.parse_default_env().init();
 }
 
+/// How to print the errors that were found.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable text, printed as soon as each error is found
+    Human,
+    /// A single JSON document, printed once all directories have been checked
+    Json,
+    /// A single SARIF 2.1.0 log, printed once all directories have been checked
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!("unknown format '{}'", s)),
+        }
+    }
+}
+
+/// Prints all errors in `report`.
+pub fn print_report(format: Format, report: &Report) {
+    match format {
+        Format::Human => {
+            for (_, file) in report.files() {
+                println!("{}", file);
+            }
+        }
+        Format::Json => println!("{}", report.to_json()),
+        Format::Sarif => println!("{}", report.to_sarif()),
+    }
+}
+
+/// Arguments for suppressing known errors.
+#[derive(Debug, Deserialize)]
+pub struct BaselineArgs {
+    pub arg_baseline: Option<PathBuf>,
+    pub arg_write_baseline: Option<PathBuf>,
+}
+
+impl BaselineArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        let path = |s: &std::ffi::OsStr| Result::<_, Error>::Ok(PathBuf::from(s));
+        Ok(BaselineArgs {
+            arg_baseline: args.opt_value_from_os_str("--baseline", path)?,
+            arg_write_baseline: args.opt_value_from_os_str("--write-baseline", path)?,
+        })
+    }
+
+    /// Whether all errors have to be collected before any can be printed.
+    pub fn is_active(&self) -> bool {
+        self.arg_baseline.is_some() || self.arg_write_baseline.is_some()
+    }
+
+    /// Writes the new baseline and removes the errors in the existing baseline from `report`.
+    ///
+    /// If only `--write-baseline` was passed, all errors are removed, since they were just recorded.
+    /// Exits the process if either file can't be read or written.
+    pub fn apply(&self, report: &mut Report) {
+        let mut known = None;
+        if let Some(path) = &self.arg_write_baseline {
+            let baseline = Baseline::from_report(report);
+            if let Err(err) = baseline.save(path) {
+                eprintln!(
+                    "error: could not write baseline {}: {}",
+                    path.display(),
+                    err
+                );
+                process::exit(1);
+            }
+            eprintln!(
+                "note: wrote {} known errors to {}",
+                baseline.len(),
+                path.display()
+            );
+            known = Some(baseline);
+        }
+        if let Some(path) = &self.arg_baseline {
+            let baseline = Baseline::load(path).unwrap_or_else(|err| {
+                eprintln!("error: could not read baseline {}: {}", path.display(), err);
+                process::exit(1);
+            });
+            let fixed = baseline.filter(report);
+            if !fixed.is_empty() {
+                eprintln!(
+                    "note: {} errors in the baseline {} no longer occur and can be removed:",
+                    fixed.len(),
+                    path.display()
+                );
+                for entry in fixed {
+                    eprintln!("\t{}", entry);
+                }
+            }
+        } else if let Some(baseline) = known {
+            baseline.filter(report);
+        }
+    }
+}
+
+/// Arguments for rewriting broken links in the sources.
+#[derive(Debug, Deserialize)]
+pub struct FixArgs {
+    pub flag_fix: bool,
+    pub flag_dry_run: bool,
+}
+
+impl FixArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        let fix = FixArgs {
+            flag_fix: args.contains("--fix"),
+            flag_dry_run: args.contains("--dry-run"),
+        };
+        if fix.flag_dry_run && !fix.flag_fix {
+            return Err(Error::ArgumentParsingFailed {
+                cause: "--dry-run can only be used with --fix".to_string(),
+            });
+        }
+        Ok(fix)
+    }
+
+    /// Whether all errors have to be collected before any can be printed.
+    pub fn is_active(&self) -> bool {
+        self.flag_fix
+    }
+
+    /// Rewrites the links in `report` which have an obvious fix in the doc comments they were written in.
+    ///
+    /// Relative source paths are resolved against `source_root`. Fixes for links whose doc comment is unknown are
+    /// looked for in the `.rs` files below `source_root`, but only printed, never applied.
+    /// With `--dry-run`, the changes are printed as a diff instead. Exits the process if a file can't be
+    /// read or written.
+    pub fn apply(&self, report: &Report, source_root: &Path) {
+        if !self.flag_fix {
+            return;
+        }
+        let fixes = cargo_deadlinks::suggest_fixes(report);
+        let (edits, suggestions) = cargo_deadlinks::find_edits(source_root, &fixes)
+            .and_then(|edits| {
+                Ok((
+                    edits,
+                    cargo_deadlinks::find_suggestions(source_root, &fixes)?,
+                ))
+            })
+            .unwrap_or_else(|err| {
+                eprintln!(
+                    "error: could not read sources in {}: {}",
+                    source_root.display(),
+                    err
+                );
+                process::exit(1);
+            });
+        for fix in &fixes {
+            let found = match &fix.source {
+                // Redirects of bare hosts have fixes with and without a trailing slash, only one of which matches.
+                Some(source) => edits.iter().any(|edit| {
+                    edit.line == source.line && edit.path == source_root.join(&source.path)
+                }),
+                None => suggestions.iter().any(|edit| edit.old.contains(&fix.old)),
+            };
+            if !found {
+                eprintln!(
+                    "note: could not find the source of '{}', which should be replaced with '{}'",
+                    fix.old, fix.new
+                );
+            }
+        }
+        if !suggestions.is_empty() {
+            for edit in &suggestions {
+                eprintln!("{}", edit);
+            }
+            eprintln!(
+                "note: {} more might need the same changes, but were not changed since it's unknown \
+                 which doc comments the broken links came from",
+                lines(suggestions.len())
+            );
+        }
+        if self.flag_dry_run {
+            for edit in &edits {
+                eprintln!("{}", edit);
+            }
+            eprintln!("note: would change {}", lines(edits.len()));
+        } else {
+            let changed = cargo_deadlinks::apply_edits(&edits).unwrap_or_else(|err| {
+                eprintln!("error: could not write fixes: {}", err);
+                process::exit(1);
+            });
+            eprintln!(
+                "note: changed {}; regenerate the documentation to check them again",
+                lines(changed)
+            );
+        }
+    }
+}
+
+/// "1 line" or "{n} lines".
+fn lines(n: usize) -> String {
+    if n == 1 {
+        "1 line".to_string()
+    } else {
+        format!("{} lines", n)
+    }
+}
+
+/// Parses a flag which turns a setting on, and the flag which turns it off again.
+///
+/// Returns `None` if neither was passed, so that the setting is left to the configuration file.
+pub fn parse_toggle(
+    args: &mut Arguments,
+    on: &'static str,
+    off: &'static str,
+) -> Result<Option<bool>, Error> {
+    match (args.contains(on), args.contains(off)) {
+        (true, true) => Err(Error::ArgumentParsingFailed {
+            cause: format!("{} and {} are mutually incompatible", on, off),
+        }),
+        (true, false) => Ok(Some(true)),
+        (false, true) => Ok(Some(false)),
+        (false, false) => Ok(None),
+    }
+}
+
+/// Parses `--check-http`, `--forbid-http` and `--no-check-http`, at most one of which may be passed.
+pub fn parse_http_check(args: &mut Arguments) -> Result<Option<HttpCheck>, Error> {
+    let flags = [
+        ("--check-http", HttpCheck::Enabled),
+        ("--forbid-http", HttpCheck::Forbidden),
+        ("--no-check-http", HttpCheck::Ignored),
+    ];
+    let passed: Vec<_> = flags
+        .iter()
+        .filter(|(flag, _)| args.contains(*flag))
+        .collect();
+    match passed.as_slice() {
+        [] => Ok(None),
+        [(_, check)] => Ok(Some(*check)),
+        [(first, _), (second, _), ..] => Err(Error::ArgumentParsingFailed {
+            cause: format!("{} and {} are mutually incompatible", first, second),
+        }),
+    }
+}
+
+/// Arguments adding ignore rules.
+#[derive(Debug, Deserialize)]
+pub struct IgnoreArgs {
+    pub arg_ignore_link: Vec<IgnorePattern>,
+    pub arg_ignore_file: Vec<IgnorePattern>,
+}
+
+impl IgnoreArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        Ok(IgnoreArgs {
+            arg_ignore_link: args.values_from_str("--ignore-link")?,
+            arg_ignore_file: args.values_from_str("--ignore-file")?,
+        })
+    }
+}
+
+/// Arguments describing where the documentation is deployed.
+#[derive(Debug, Deserialize)]
+pub struct SiteArgs {
+    pub arg_site_root: Option<String>,
+    pub arg_base_url: Option<Url>,
+}
+
+impl SiteArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        Ok(SiteArgs {
+            arg_site_root: args.opt_value_from_fn("--site-root", parse_site_root)?,
+            arg_base_url: args.opt_value_from_fn("--base-url", Url::parse)?,
+        })
+    }
+}
+
+/// Arguments for finding pages which can't be reached from the entry points.
+#[derive(Debug, Deserialize)]
+pub struct OrphanArgs {
+    pub flag_orphan_pages: Option<bool>,
+    pub arg_entry_point: Vec<String>,
+}
+
+impl OrphanArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        Ok(OrphanArgs {
+            flag_orphan_pages: parse_toggle(args, "--orphan-pages", "--no-orphan-pages")?,
+            arg_entry_point: args.values_from_str("--entry-point")?,
+        })
+    }
+}
+
+fn parse_site_root(path: &str) -> Result<String, String> {
+    if path.starts_with('/') {
+        Ok(path.to_owned())
+    } else {
+        Err(format!(
+            "the site root must be an absolute path like '/docs/', not '{}'",
+            path
+        ))
+    }
+}
+
+/// Arguments limiting how many HTTP requests are sent, and how failed requests are handled.
+#[derive(Debug, Deserialize)]
+pub struct HttpLimitArgs {
+    pub arg_max_http_requests: Option<usize>,
+    pub arg_max_http_requests_per_host: Option<usize>,
+    pub arg_http_rate_limit: Option<f64>,
+    pub arg_http_retries: Option<u32>,
+    pub arg_http_retry_delay: Option<Duration>,
+    pub flag_deny_transient_errors: Option<bool>,
+}
+
+impl HttpLimitArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        Ok(HttpLimitArgs {
+            arg_max_http_requests: args.opt_value_from_str("--max-http-requests")?,
+            arg_max_http_requests_per_host: args
+                .opt_value_from_str("--max-http-requests-per-host")?,
+            arg_http_rate_limit: args.opt_value_from_str("--http-rate-limit")?,
+            arg_http_retries: args.opt_value_from_str("--http-retries")?,
+            arg_http_retry_delay: args
+                .opt_value_from_fn("--http-retry-delay", cargo_deadlinks::parse_duration)?,
+            flag_deny_transient_errors: parse_toggle(
+                args,
+                "--deny-transient-errors",
+                "--allow-transient-errors",
+            )?,
+        })
+    }
+}
+
+/// Arguments configuring the HTTP client and how redirects are reported.
+#[derive(Debug, Deserialize)]
+pub struct HttpClientArgs {
+    pub arg_max_redirects: Option<u32>,
+    pub flag_deny_cross_host_redirects: Option<bool>,
+    pub arg_http_connect_timeout: Option<Duration>,
+    pub arg_http_read_timeout: Option<Duration>,
+    pub arg_user_agent: Option<String>,
+    pub arg_http_header: Vec<HttpHeader>,
+    pub arg_http_proxy: Option<String>,
+    pub arg_http_ca_bundle: Option<PathBuf>,
+}
+
+impl HttpClientArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        Ok(HttpClientArgs {
+            arg_max_redirects: args.opt_value_from_str("--max-redirects")?,
+            flag_deny_cross_host_redirects: parse_toggle(
+                args,
+                "--deny-cross-host-redirects",
+                "--allow-cross-host-redirects",
+            )?,
+            arg_http_connect_timeout: args
+                .opt_value_from_fn("--http-connect-timeout", cargo_deadlinks::parse_duration)?,
+            arg_http_read_timeout: args
+                .opt_value_from_fn("--http-read-timeout", cargo_deadlinks::parse_duration)?,
+            arg_user_agent: args.opt_value_from_str("--user-agent")?,
+            arg_http_header: args.values_from_str("--http-header")?,
+            arg_http_proxy: args.opt_value_from_str("--http-proxy")?,
+            arg_http_ca_bundle: args.opt_value_from_os_str("--http-ca-bundle", |s| {
+                Result::<_, Error>::Ok(PathBuf::from(s))
+            })?,
+        })
+    }
+}
+
+/// Exits the process if HTTP links will be checked, but the HTTP client can't be created.
+pub fn validate_http_client(ctx: &CheckContext) {
+    if ctx.check_http != HttpCheck::Enabled {
+        return;
+    }
+    if let Err(err) = ctx.http_client.validate() {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Whether `path` is a rustdoc JSON file rather than a directory of HTML files.
+pub fn is_rustdoc_json(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == "json")
+}
+
+/// Checks a rustdoc JSON file, exiting the process if it can't be read.
+pub fn check_rustdoc_json(path: &Path, source_root: &Path, ctx: &CheckContext) -> Vec<FileError> {
+    cargo_deadlinks::check_rustdoc_json(path, source_root, ctx).unwrap_or_else(|err| {
+        eprintln!(
+            "error: could not read rustdoc JSON {}: {}",
+            path.display(),
+            err
+        );
+        process::exit(1);
+    })
+}
+
+/// Arguments controlling the persistent HTTP cache.
+#[derive(Debug, Deserialize)]
+pub struct HttpCacheArgs {
+    pub arg_http_cache: Option<PathBuf>,
+    pub arg_http_cache_ttl: Option<Duration>,
+    pub arg_http_cache_failure_ttl: Option<Duration>,
+    pub flag_refresh_http_cache: bool,
+    pub flag_clear_http_cache: bool,
+}
+
+impl HttpCacheArgs {
+    pub fn parse(args: &mut Arguments) -> Result<Self, Error> {
+        Ok(HttpCacheArgs {
+            arg_http_cache: args.opt_value_from_os_str("--http-cache", |s| {
+                Result::<_, Error>::Ok(PathBuf::from(s))
+            })?,
+            arg_http_cache_ttl: args
+                .opt_value_from_fn("--http-cache-ttl", cargo_deadlinks::parse_duration)?,
+            arg_http_cache_failure_ttl: args
+                .opt_value_from_fn("--http-cache-failure-ttl", cargo_deadlinks::parse_duration)?,
+            flag_refresh_http_cache: args.contains("--refresh-http-cache"),
+            flag_clear_http_cache: args.contains("--clear-http-cache"),
+        })
+    }
+}
+
+/// Loads the HTTP cache configured in `config`, if any. Exits the process if it can't be read.
+pub fn load_http_cache(config: &Config, args: &HttpCacheArgs) -> Option<Arc<HttpCache>> {
+    let path = config.http_cache.as_ref()?;
+    let mut cache = if args.flag_clear_http_cache {
+        HttpCache::new(path)
+    } else {
+        HttpCache::load(path).unwrap_or_else(|err| {
+            eprintln!(
+                "error: could not read HTTP cache {}: {}",
+                path.display(),
+                err
+            );
+            process::exit(1);
+        })
+    };
+    if let Some(ttl) = config.http_cache_ttl {
+        cache.success_ttl = ttl;
+    }
+    if let Some(ttl) = config.http_cache_failure_ttl {
+        cache.failure_ttl = ttl;
+    }
+    cache.refresh = args.flag_refresh_http_cache;
+    Some(Arc::new(cache))
+}
+
+/// The documentation of the standard library to check links to doc.rust-lang.org against, if `config` enables
+/// `local-docs`.
+///
+/// The documentation is found with `rustup doc --path`, only once. If that fails, a warning is printed and the
+/// links are assumed to be valid, as they are without `local-docs`.
+pub fn std_docs(config: &Config) -> Option<PathBuf> {
+    static STD_DOCS: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+    if config.local_docs != Some(true) {
+        return None;
+    }
+    STD_DOCS
+        .get_or_init(|| {
+            let output = Command::new("rustup").args(["doc", "--path"]).output();
+            let dir = match output {
+                Ok(output) if output.status.success() => {
+                    let index = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+                    index.parent().filter(|dir| dir.is_dir()).map(Path::to_owned)
+                }
+                _ => None,
+            };
+            if dir.is_none() {
+                eprintln!("warning: could not find the standard library's documentation with `rustup doc --path`");
+                eprintln!("help: install it with `rustup component add rust-docs`");
+            }
+            dir
+        })
+        .clone()
+}
+
+/// Saves the HTTP cache to disk, giving a warning if it fails.
+pub fn save_http_cache(cache: Option<&HttpCache>) {
+    if let Some(cache) = cache {
+        if let Err(err) = cache.save() {
+            eprintln!(
+                "warning: could not save HTTP cache to {}: {}",
+                cache.path().display(),
+                err
+            );
+        }
+    }
+}
+
+/// Reads the configuration file passed with `--config`, or `deadlinks.toml` in `dir` if there is one.
+///
+/// Exits the process if the configuration is invalid.
+pub fn read_config_file(explicit: Option<&Path>, dir: &Path) -> Config {
+    let config = match explicit {
+        Some(path) => Config::from_file(path).map(Some),
+        None => Config::from_dir(dir),
+    };
+    unwrap_config(config)
+}
+
+/// Returns the configuration, or the default configuration if there was none.
+///
+/// Exits the process if the configuration could not be read.
+pub fn unwrap_config(config: Result<Option<Config>, ConfigError>) -> Config {
+    match config {
+        Ok(config) => config.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// How many links and files were skipped by each ignore rule, summed over all checked directories.
+#[derive(Debug, Default)]
+pub struct IgnoredCounts {
+    links: Vec<(String, usize)>,
+    files: Vec<(String, usize)>,
+}
+
+impl IgnoredCounts {
+    /// Adds the counts from a context that has finished checking a directory.
+    pub fn add(&mut self, ctx: &CheckContext) {
+        fn add_list(totals: &mut Vec<(String, usize)>, list: &IgnoreList) {
+            for (pattern, count) in list.counts() {
+                match totals.iter_mut().find(|(p, _)| p == pattern.as_str()) {
+                    Some((_, total)) => *total += count,
+                    None => totals.push((pattern.to_string(), count)),
+                }
+            }
+        }
+        add_list(&mut self.links, &ctx.ignore_links);
+        add_list(&mut self.files, &ctx.ignore_files);
+    }
+
+    pub fn print(&self) {
+        for (pattern, count) in &self.links {
+            eprintln!("note: ignored {} links matching '{}'", count, pattern);
+        }
+        for (pattern, count) in &self.files {
+            eprintln!(
+                "note: skipped {} files or directories matching '{}'",
+                count, pattern
+            );
+        }
+    }
+}
+
+/// Parses a comma-separated list of link kinds, e.g. `hyperlink,media`.
+pub fn parse_link_kinds(kinds: &str) -> Result<HashSet<LinkKind>, String> {
+    kinds.split(',').map(|kind| kind.trim().parse()).collect()
+}
+
 // See https://github.com/RazrFalcon/pico-args/pull/26
 pub struct PicoError(pub Error);
 
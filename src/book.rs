@@ -0,0 +1,187 @@
+//! Checking books written with [mdBook](https://rust-lang.github.io/mdBook/).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::{check_dir, check_markdown, CheckContext, Config, ConfigError, FileError};
+
+/// The page mdBook renders every chapter into, one after the other.
+const PRINT_PAGE: &str = "print.html";
+
+/// The parts of `book.toml` deadlinks needs.
+#[derive(Default, Deserialize)]
+struct BookToml {
+    #[serde(default)]
+    book: BookSection,
+    #[serde(default)]
+    build: BuildSection,
+    /// The settings of each renderer, by name.
+    #[serde(default)]
+    output: Map<String, Value>,
+}
+
+#[derive(Default, Deserialize)]
+struct BookSection {
+    src: Option<PathBuf>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BuildSection {
+    build_dir: Option<PathBuf>,
+}
+
+/// An mdBook, as described by its `book.toml`.
+#[derive(Clone, Debug)]
+pub struct Book {
+    /// The directory containing `book.toml`.
+    pub root: PathBuf,
+    /// The directory containing the Markdown source of the chapters (`book.src`).
+    pub src_dir: PathBuf,
+    /// The directory the HTML renderer writes the book to.
+    pub html_dir: PathBuf,
+    /// The path the book is served under (`output.html.site-url`), e.g. `/my-book/`.
+    pub site_root: String,
+    /// The URL the book is deployed at, if `output.html.site-url` is a full URL.
+    pub base_url: Option<Url>,
+    /// The settings in `[output.deadlinks]`, if there are any.
+    pub config: Option<Config>,
+}
+
+impl Book {
+    /// Read `book.toml` in `root`, which should be an absolute path.
+    pub fn load(root: &Path) -> Result<Book, ConfigError> {
+        let path = root.join("book.toml");
+        let contents =
+            fs::read_to_string(&path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+        let book = toml::from_str(&contents)
+            .map_err(|err| ConfigError::Invalid(path.display().to_string(), err.to_string()))?;
+        Book::new(root, book)
+    }
+
+    /// Read the JSON context mdBook passes to renderers on standard input.
+    pub fn from_render_context(context: &str) -> Result<Book, ConfigError> {
+        #[derive(Deserialize)]
+        struct RenderContext {
+            root: PathBuf,
+            config: BookToml,
+        }
+
+        let context: RenderContext = serde_json::from_str(context).map_err(|err| {
+            ConfigError::Invalid("the mdBook render context".to_owned(), err.to_string())
+        })?;
+        Book::new(&context.root, context.config)
+    }
+
+    fn new(root: &Path, book: BookToml) -> Result<Book, ConfigError> {
+        let build_dir = root.join(book.build.build_dir.unwrap_or_else(|| "book".into()));
+        // With more than one renderer, each one writes to a subdirectory named after it.
+        let html_dir = if book.output.len() > 1 {
+            build_dir.join("html")
+        } else {
+            build_dir
+        };
+        let site_url = book
+            .output
+            .get("html")
+            .and_then(|html| html.get("site-url"))
+            .and_then(Value::as_str)
+            .unwrap_or("/");
+        let base_url = Url::parse(site_url).ok();
+        let site_root = match &base_url {
+            Some(url) => url.path().to_owned(),
+            None => site_url.to_owned(),
+        };
+        let config = match book.output.get("deadlinks") {
+            Some(table) => Some(Config::from_book(table, root)?),
+            None => None,
+        };
+        Ok(Book {
+            root: root.to_owned(),
+            src_dir: root.join(book.book.src.unwrap_or_else(|| "src".into())),
+            html_dir,
+            site_root,
+            base_url,
+            config,
+        })
+    }
+
+    /// Checks the HTML output of the book, which has to be built first.
+    ///
+    /// Absolute links are resolved relative to [`Book::site_root`], and links to [`Book::base_url`] are checked
+    /// locally as well, unless `ctx` sets a different site root or base URL. `print.html` repeats every chapter,
+    /// so errors in it are not reported; they are reported for the chapter they were written in instead.
+    /// Unless `ctx.verbose` is set, paths are relative to [`Book::html_dir`].
+    pub fn check_output(&self, ctx: &CheckContext) -> Vec<FileError> {
+        let ctx = if ctx.site_root.is_some() || ctx.base_url.is_some() {
+            ctx.clone()
+        } else {
+            CheckContext {
+                site_root: Some(self.site_root.clone()),
+                base_url: self.base_url.clone(),
+                ..ctx.clone()
+            }
+        };
+        let mut files = check_dir(&self.html_dir, &ctx);
+        files.retain(|file| {
+            file.path.strip_prefix(&self.html_dir).unwrap_or(&file.path) != Path::new(PRINT_PAGE)
+        });
+        files
+    }
+
+    /// Checks the Markdown source of the book, without building it.
+    ///
+    /// mdBook generates the same anchors for headings as GitHub, so fragments are checked like in
+    /// [`check_markdown`]. Unless `ctx.verbose` is set, paths are relative to [`Book::src_dir`].
+    pub fn check_sources(&self, ctx: &CheckContext) -> Vec<FileError> {
+        check_markdown(&self.src_dir, ctx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::Book;
+    use crate::HttpCheck;
+
+    #[test]
+    fn test_render_context() {
+        let context = serde_json::json!({
+            "version": "0.4.48",
+            "root": "/books/guide",
+            "book": { "sections": [] },
+            "config": {
+                "book": { "src": "chapters" },
+                "output": {
+                    "html": { "site-url": "https://example.com/guide/" },
+                    "deadlinks": { "command": "mdbook-deadlinks", "http": "forbidden" },
+                },
+            },
+            "destination": "/books/guide/book/deadlinks",
+        });
+        let book = Book::from_render_context(&context.to_string()).unwrap();
+        assert_eq!(book.src_dir, Path::new("/books/guide/chapters"));
+        assert_eq!(book.html_dir, Path::new("/books/guide/book/html"));
+        assert_eq!(book.site_root, "/guide/");
+        assert_eq!(
+            book.base_url.unwrap().as_str(),
+            "https://example.com/guide/"
+        );
+        assert_eq!(book.config.unwrap().http, Some(HttpCheck::Forbidden));
+    }
+
+    #[test]
+    fn test_default_book_toml() {
+        let book = Book::new(Path::new("/books/guide"), toml::from_str("").unwrap()).unwrap();
+        assert_eq!(book.src_dir, Path::new("/books/guide/src"));
+        assert_eq!(book.html_dir, Path::new("/books/guide/book"));
+        assert_eq!(book.site_root, "/");
+        assert!(book.base_url.is_none());
+        assert!(book.config.is_none());
+    }
+}
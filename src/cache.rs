@@ -0,0 +1,253 @@
+//! A persistent, on-disk cache of HTTP check results.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+use url::Url;
+
+use crate::http::Redirect;
+
+/// The version of the cache file format. Files with a different version are ignored.
+const CACHE_VERSION: u32 = 2;
+
+/// The result of an earlier HTTP request, as stored in the cache.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CachedResponse {
+    pub status: u16,
+    pub status_text: String,
+    /// When the URL was checked, in seconds since the Unix epoch.
+    pub checked_at: u64,
+    /// The ids of all elements in the page, if it was fetched with a GET request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragments: Option<HashSet<String>>,
+    /// The redirects that were followed to get the response.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirects: Vec<Redirect>,
+}
+
+impl CachedResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, CachedResponse>,
+}
+
+/// A persistent cache of HTTP check results, keyed by URL.
+///
+/// Load the cache with [`HttpCache::load`], put it in [`crate::CheckContext::http_cache`],
+/// and call [`HttpCache::save`] once all directories have been checked.
+#[derive(Debug)]
+pub struct HttpCache {
+    path: PathBuf,
+    /// How long a successful response is reused before the URL is checked again.
+    pub success_ttl: Duration,
+    /// How long a failed response is reused before the URL is checked again.
+    pub failure_ttl: Duration,
+    /// Ignore existing entries, but still record new results.
+    pub refresh: bool,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl HttpCache {
+    /// The default value of [`HttpCache::success_ttl`]: one day.
+    pub const DEFAULT_SUCCESS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    /// The default value of [`HttpCache::failure_ttl`]: one hour.
+    pub const DEFAULT_FAILURE_TTL: Duration = Duration::from_secs(60 * 60);
+
+    /// Create an empty cache which will be saved to `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        HttpCache {
+            path: path.into(),
+            success_ttl: Self::DEFAULT_SUCCESS_TTL,
+            failure_ttl: Self::DEFAULT_FAILURE_TTL,
+            refresh: false,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load the cache stored at `path`.
+    ///
+    /// If the file does not exist or was written by an incompatible version of deadlinks,
+    /// this returns an empty cache.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let cache = Self::new(path);
+        let contents = match fs::read_to_string(&cache.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(err) => return Err(err),
+        };
+        match serde_json::from_str::<CacheFile>(&contents) {
+            Ok(file) if file.version == CACHE_VERSION => {
+                debug!(
+                    "loaded {} cached HTTP results from {}",
+                    file.entries.len(),
+                    cache.path.display()
+                );
+                *cache.entries.lock().unwrap() = file.entries;
+            }
+            Ok(file) => warn!(
+                "ignoring HTTP cache {} with unsupported version {}",
+                cache.path.display(),
+                file.version
+            ),
+            Err(err) => warn!(
+                "ignoring invalid HTTP cache {}: {}",
+                cache.path.display(),
+                err
+            ),
+        }
+        Ok(cache)
+    }
+
+    /// The file this cache is stored in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write all entries which have not yet expired to disk, creating parent directories as necessary.
+    pub fn save(&self) -> io::Result<()> {
+        let now = now();
+        let entries = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, response)| !self.is_expired(response, now))
+            .map(|(url, response)| (url.clone(), response.clone()))
+            .collect();
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first so an interrupted run doesn't leave a truncated cache behind.
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(&file)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Return the cached response for `url` (ignoring the fragment), if it has not yet expired.
+    pub(crate) fn get(&self, url: &Url) -> Option<CachedResponse> {
+        if self.refresh {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap();
+        let response = entries.get(&cache_key(url))?;
+        if self.is_expired(response, now()) {
+            None
+        } else {
+            debug!("using cached HTTP status {} for {}", response.status, url);
+            Some(response.clone())
+        }
+    }
+
+    /// Record the response for `url`.
+    pub(crate) fn insert(
+        &self,
+        url: &Url,
+        status: u16,
+        status_text: &str,
+        fragments: Option<HashSet<String>>,
+        redirects: &[Redirect],
+    ) {
+        let response = CachedResponse {
+            status,
+            status_text: status_text.to_owned(),
+            checked_at: now(),
+            fragments,
+            redirects: redirects.to_vec(),
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(cache_key(url), response);
+    }
+
+    fn is_expired(&self, response: &CachedResponse, now: u64) -> bool {
+        let ttl = if response.is_success() {
+            self.success_ttl
+        } else {
+            self.failure_ttl
+        };
+        now.saturating_sub(response.checked_at) >= ttl.as_secs()
+    }
+}
+
+fn cache_key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.into()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use url::Url;
+
+    use super::HttpCache;
+
+    #[test]
+    fn test_ttl() {
+        let mut cache = HttpCache::new("unused.json");
+        let ok = Url::parse("https://example.com/ok#fragment").unwrap();
+        let missing = Url::parse("https://example.com/missing").unwrap();
+        cache.insert(&ok, 200, "OK", None, &[]);
+        cache.insert(&missing, 404, "Not Found", None, &[]);
+
+        assert_eq!(
+            cache
+                .get(&Url::parse("https://example.com/ok").unwrap())
+                .unwrap()
+                .status,
+            200
+        );
+        assert_eq!(cache.get(&missing).unwrap().status, 404);
+
+        cache.failure_ttl = Duration::from_secs(0);
+        assert!(cache.get(&ok).is_some());
+        assert!(cache.get(&missing).is_none());
+
+        cache.refresh = true;
+        assert!(cache.get(&ok).is_none());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir()
+            .join(format!("deadlinks-test-{}", std::process::id()))
+            .join("http-cache.json");
+        let url = Url::parse("https://example.com/page").unwrap();
+
+        let cache = HttpCache::new(&path);
+        let fragments = vec!["a".to_owned(), "b".to_owned()].into_iter().collect();
+        cache.insert(&url, 200, "OK", Some(fragments), &[]);
+        cache.save().unwrap();
+
+        let loaded = HttpCache::load(&path).unwrap();
+        let response = loaded.get(&url).unwrap();
+        assert_eq!(response.status, 200);
+        assert!(response.fragments.unwrap().contains("b"));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
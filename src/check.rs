@@ -1,12 +1,14 @@
 //! Provides functionality for checking the availablility of URLs.
 use std::collections::HashSet;
 use std::fmt;
-use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use log::{debug, info, warn};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::ser::{Serialize, Serializer};
+use serde_derive::Serialize;
 use url::Url;
 
 use cached::cached_key_result;
@@ -15,30 +17,93 @@ use cached::SizedCache;
 use super::CheckContext;
 
 use crate::{
-    parse::{parse_fragments, parse_redirect},
-    HttpCheck,
+    cache::CachedResponse,
+    http::{Fetched, HttpClient, Redirect},
+    parse::{extract_page, PageError},
+    HttpCheck, SiteIndex,
 };
 
 const PREFIX_BLACKLIST: [&str; 1] = ["https://doc.rust-lang.org"];
 
-#[derive(Debug)]
+// NOTE: the errors are reference-counted so that the result of checking a URL can be shared
+// between all the files that link to it.
+#[derive(Clone, Debug)]
 pub enum IoError {
-    HttpUnexpectedStatus(ureq::Response),
-    HttpFetch(ureq::Transport),
-    FileIo(String, std::io::Error),
+    HttpUnexpectedStatus {
+        url: String,
+        status: u16,
+        status_text: String,
+    },
+    /// The request could not be sent or the response could not be read.
+    /// `ureq::Transport` doesn't expose its kind, so it's stored separately.
+    HttpFetch(Arc<ureq::Transport>, ureq::ErrorKind),
+    FileIo(String, Arc<std::io::Error>),
+    /// The URL was not checked because the HTTP client could not be set up, e.g. because the proxy is invalid
+    /// or the CA bundle can't be read. See [`HttpClientConfig::validate`](crate::HttpClientConfig::validate).
+    HttpClient(String, Arc<std::io::Error>),
+}
+
+/// Whether a response with this status might succeed if the request is sent again later.
+pub(crate) fn is_transient_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// Whether a request that failed with this error might succeed if it is sent again later.
+pub(crate) fn is_transient_transport(kind: ureq::ErrorKind) -> bool {
+    matches!(
+        kind,
+        ureq::ErrorKind::Io | ureq::ErrorKind::ConnectionFailed
+    )
+}
+
+impl IoError {
+    /// Whether the link is definitely broken: the server said the page was not found (404) or is gone (410).
+    pub fn is_definite(&self) -> bool {
+        matches!(
+            self,
+            IoError::HttpUnexpectedStatus {
+                status: 404 | 410,
+                ..
+            }
+        )
+    }
+
+    /// Whether the error might go away if the link is checked again later,
+    /// e.g. because of a timeout, a reset connection, rate limiting (429) or a server error (5xx).
+    pub fn is_transient(&self) -> bool {
+        match self {
+            IoError::HttpUnexpectedStatus { status, .. } => is_transient_status(*status),
+            IoError::HttpFetch(_, kind) => is_transient_transport(*kind),
+            IoError::FileIo(..) | IoError::HttpClient(..) => false,
+        }
+    }
+
+    fn unexpected_status(url: &Url, response: &CachedResponse) -> Self {
+        IoError::HttpUnexpectedStatus {
+            url: url.to_string(),
+            status: response.status,
+            status_text: response.status_text.clone(),
+        }
+    }
 }
 
 impl fmt::Display for IoError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            IoError::HttpUnexpectedStatus(resp) => write!(
+            IoError::HttpUnexpectedStatus {
+                url, status_text, ..
+            } => write!(
                 f,
                 "Unexpected HTTP status fetching {}: {}",
-                resp.get_url(),
-                resp.status_text()
+                url, status_text
             ),
-            IoError::HttpFetch(e) => write!(f, "Error fetching {}", e),
+            IoError::HttpFetch(e, _) => write!(f, "Error fetching {}", e),
             IoError::FileIo(url, e) => write!(f, "Error fetching {}: {}", url, e),
+            IoError::HttpClient(url, e) => write!(
+                f,
+                "Could not check {}: the HTTP client could not be set up: {}",
+                url, e
+            ),
         }
     }
 }
@@ -58,6 +123,12 @@ impl fmt::Display for Link {
     }
 }
 
+impl Serialize for Link {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl Link {
     /// Removes the fragment
     fn without_fragment(&self) -> Link {
@@ -73,7 +144,7 @@ impl Link {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum CheckError {
     /// An intra-doc link went unresolved by rustdoc and ended up in the final HTML
     IntraDocLink(String),
@@ -85,20 +156,158 @@ pub enum CheckError {
     HttpForbidden(Url),
     /// The linked file existed, but was missing the linked HTML anchor
     Fragment(Link, String, Option<Vec<String>>),
+    /// A linked HTTP URL permanently redirects (301 or 308) somewhere else, so the link should be updated
+    PermanentRedirect(Url, Vec<Redirect>),
+    /// A linked HTTP URL redirected more often than allowed by
+    /// [`HttpClientConfig::max_redirects`](crate::HttpClientConfig::max_redirects)
+    TooManyRedirects(Url, Vec<Redirect>),
+    /// A linked HTTP URL redirected to a different host, and
+    /// [`CheckContext::deny_cross_host_redirects`] was set
+    CrossHostRedirect(Url, Vec<Redirect>),
     /// An error occured while trying to find whether the file or URL existed
     Io(Box<IoError>),
+    /// The file being checked could not be read, e.g. because it is not UTF-8 and doesn't declare its encoding
+    UnreadableFile(PathBuf, Arc<std::io::Error>),
+    /// The HTML of a page could not be parsed, either the file being checked or a linked page whose fragments
+    /// were needed
+    Parse(Link, String),
+    /// The page can't be reached by following links from any of the entry points, see
+    /// [`CheckContext::check_orphan_pages`]
+    OrphanPage(PathBuf),
 }
 
 impl From<ureq::Error> for CheckError {
     fn from(err: ureq::Error) -> Self {
+        let kind = err.kind();
         let io_err = match err {
-            ureq::Error::Status(_, response) => IoError::HttpUnexpectedStatus(response),
-            ureq::Error::Transport(err) => IoError::HttpFetch(err),
+            ureq::Error::Status(status, response) => IoError::HttpUnexpectedStatus {
+                url: response.get_url().to_owned(),
+                status,
+                status_text: response.status_text().to_owned(),
+            },
+            ureq::Error::Transport(err) => IoError::HttpFetch(Arc::new(err), kind),
         };
         CheckError::Io(Box::new(io_err))
     }
 }
 
+impl CheckError {
+    /// A [`CheckError::Parse`] for the page at `link`.
+    ///
+    /// Only the first line of the parser's error is kept; the rest explains the limits of streaming parsers.
+    pub(crate) fn parse(link: Link, err: &dyn fmt::Display) -> Self {
+        let message = err.to_string();
+        CheckError::Parse(link, message.lines().next().unwrap_or_default().to_owned())
+    }
+
+    /// A short, stable identifier for this kind of error.
+    ///
+    /// This is used as the `kind` field in JSON reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CheckError::IntraDocLink(_) => "intra-doc-link",
+            CheckError::File(_) => "missing-file",
+            CheckError::Fragment(..) => "missing-fragment",
+            CheckError::Http(_) => "http-error",
+            CheckError::HttpForbidden(_) => "http-forbidden",
+            CheckError::PermanentRedirect(..) => "permanent-redirect",
+            CheckError::TooManyRedirects(..) => "too-many-redirects",
+            CheckError::CrossHostRedirect(..) => "cross-host-redirect",
+            CheckError::Io(_) => "io",
+            CheckError::UnreadableFile(..) => "unreadable-file",
+            CheckError::Parse(..) => "parse-error",
+            CheckError::OrphanPage(_) => "orphan-page",
+        }
+    }
+
+    /// The link that caused this error, if known.
+    pub fn link(&self) -> Option<String> {
+        match self {
+            CheckError::IntraDocLink(text) => Some(text.clone()),
+            CheckError::File(path)
+            | CheckError::UnreadableFile(path, _)
+            | CheckError::OrphanPage(path) => Some(path.display().to_string()),
+            CheckError::Http(url)
+            | CheckError::HttpForbidden(url)
+            | CheckError::PermanentRedirect(url, _)
+            | CheckError::TooManyRedirects(url, _)
+            | CheckError::CrossHostRedirect(url, _) => Some(url.to_string()),
+            CheckError::Fragment(link, _, _) | CheckError::Parse(link, _) => Some(link.to_string()),
+            CheckError::Io(err) => match &**err {
+                IoError::HttpUnexpectedStatus { url, .. } => Some(url.clone()),
+                IoError::HttpFetch(..) => None,
+                IoError::FileIo(url, _) | IoError::HttpClient(url, _) => Some(url.clone()),
+            },
+        }
+    }
+
+    /// Whether the error might go away if the link is checked again later. See [`IoError::is_transient`].
+    pub fn is_transient(&self) -> bool {
+        match self {
+            CheckError::Io(err) => err.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// The redirects that were followed when checking the link, if the error is about them.
+    pub fn redirects(&self) -> Option<&[Redirect]> {
+        match self {
+            CheckError::PermanentRedirect(_, redirects)
+            | CheckError::TooManyRedirects(_, redirects)
+            | CheckError::CrossHostRedirect(_, redirects) => Some(redirects),
+            _ => None,
+        }
+    }
+
+    /// The HTTP status code returned by the server, if any.
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            CheckError::Io(err) => match &**err {
+                IoError::HttpUnexpectedStatus { status, .. } => Some(*status),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for CheckError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct SerializedError<'a> {
+            kind: &'static str,
+            message: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            link: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            fragment: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            missing_fragments: Option<&'a [String]>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            http_status: Option<u16>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            redirects: Option<&'a [Redirect]>,
+        }
+
+        let (fragment, missing_fragments) = match self {
+            CheckError::Fragment(_, fragment, missing) => {
+                (Some(fragment.as_str()), missing.as_deref())
+            }
+            _ => (None, None),
+        };
+        SerializedError {
+            kind: self.kind(),
+            message: self.to_string(),
+            link: self.link(),
+            fragment,
+            missing_fragments,
+            http_status: self.http_status(),
+            redirects: self.redirects(),
+        }
+        .serialize(serializer)
+    }
+}
+
 impl fmt::Display for CheckError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -125,53 +334,107 @@ impl fmt::Display for CheckError {
                 ),
                 None => write!(f, "Fragment #{} at {} does not exist!", fragment, link),
             },
+            CheckError::PermanentRedirect(url, redirects) => write!(
+                f,
+                "Linked URL {} permanently redirects to {}",
+                url,
+                final_location(url, redirects)
+            ),
+            CheckError::TooManyRedirects(url, redirects) => write!(
+                f,
+                "Linked URL {} redirected too many times (last to {})",
+                url,
+                final_location(url, redirects)
+            ),
+            CheckError::CrossHostRedirect(url, redirects) => write!(
+                f,
+                "Linked URL {} redirects to a different host: {}",
+                url,
+                final_location(url, redirects)
+            ),
             CheckError::Io(err) => err.fmt(f),
+            CheckError::UnreadableFile(path, err) => {
+                write!(f, "Could not read {}: {}", path.display(), err)
+            }
+            CheckError::Parse(link, err) => write!(f, "Could not parse {}: {}", link, err),
+            CheckError::OrphanPage(path) => write!(
+                f,
+                "Page {} can't be reached by following links from the entry points",
+                path.display()
+            ),
         }
     }
 }
 
+fn final_location<'a>(url: &'a Url, redirects: &'a [Redirect]) -> &'a Url {
+    redirects.last().map_or(url, |redirect| &redirect.location)
+}
+
 /// Check a single URL for availability. Returns `false` if it is unavailable.
+#[cfg(test)]
 pub fn is_available(url: &Url, ctx: &CheckContext) -> Result<(), CheckError> {
+    is_available_offline(url, ctx, &SiteIndex::default())
+        .unwrap_or_else(|| fetch_http_url(url, ctx, &HttpClient::new(ctx).unwrap()))
+}
+
+/// Check a single URL for availability without sending any HTTP requests.
+///
+/// Local files are looked up in `index` first.
+/// Returns `None` for HTTP URLs which have to be fetched with [`fetch_http_url`].
+pub(crate) fn is_available_offline(
+    url: &Url,
+    ctx: &CheckContext,
+    index: &SiteIndex,
+) -> Option<Result<(), CheckError>> {
     match url.scheme() {
-        "file" => check_file_url(url, ctx),
-        "http" | "https" => check_http_url(url, ctx),
+        "file" => Some(check_file_url(url, ctx, index)),
+        "http" | "https" => {
+            check_local_docs(url, ctx, index).or_else(|| check_http_url_offline(url, ctx))
+        }
         scheme @ "javascript" => {
             debug!("Not checking URL scheme {:?}", scheme);
-            Ok(())
+            Some(Ok(()))
         }
         other => {
             debug!("Unrecognized URL scheme {:?}", other);
-            Ok(())
+            Some(Ok(()))
         }
     }
 }
 
+/// The ids in a web page, along with the redirects that were followed to fetch it.
+#[derive(Clone)]
+struct Page {
+    fragments: HashSet<String>,
+    redirects: Vec<Redirect>,
+}
+
 cached_key_result! {
-    CHECK_FILE: SizedCache<String, HashSet<String>> = SizedCache::with_size(100);
+    // Local files are in the `SiteIndex` instead.
+    HTTP_PAGES: SizedCache<String, Page> = SizedCache::with_size(100);
     Key = { link.without_fragment().to_string() };
-    // `fetch_html` is different depending on whether the link is being
-    // loaded from disk or from the network.
-    fn fragments_from(
+    fn page_from(
         link: &Link,
-        fetch_html: impl Fn() -> Result<String, CheckError>
-    ) -> Result<HashSet<String>, CheckError> = {
-        fetch_html().map(|html| parse_fragments(&html))
+        fetch_page: impl Fn() -> Result<Page, CheckError>
+    ) -> Result<Page, CheckError> = {
+        fetch_page()
     }
 }
 
+/// Whether `fragment` points at the top of the page rather than at an anchor.
+///
+/// Empty fragments (e.g. file.html#) are commonly used to reach the top
+/// of the document, see https://html.spec.whatwg.org/multipage/browsing-the-web.html#scroll-to-fragid
+fn is_top_of_page(fragment: &str) -> bool {
+    fragment.is_empty()
+}
+
+/// Check whether the page at `link`, which has the ids `fragments`, contains `fragment`.
 fn is_fragment_available(
     link: &Link,
     fragment: &str,
-    fetch_html: impl Fn() -> Result<String, CheckError>,
+    fragments: &HashSet<String>,
 ) -> Result<(), CheckError> {
-    // Empty fragments (e.g. file.html#) are commonly used to reach the top
-    // of the document, see https://html.spec.whatwg.org/multipage/browsing-the-web.html#scroll-to-fragid
-    if fragment.is_empty() {
-        return Ok(());
-    }
-
-    let fragments = fragments_from(link, fetch_html)?;
-
     if fragments.contains(fragment) {
         return Ok(());
     }
@@ -227,16 +490,16 @@ fn is_fragment_available(
 }
 
 /// Check a URL with the "file" scheme for availability. Returns `false` if it is unavailable.
-fn check_file_url(url: &Url, ctx: &CheckContext) -> Result<(), CheckError> {
+fn check_file_url(url: &Url, ctx: &CheckContext, index: &SiteIndex) -> Result<(), CheckError> {
     let path = url.to_file_path().unwrap();
 
     // determine the full path by looking if the path points to a directory,
     // and if so append `index.html`, this is needed as we'll try to read
     // the file, so `expanded_path` should point to a file not a directory
     let index_html;
-    let expanded_path = if path.is_file() {
+    let expanded_path = if index.is_file(&path) {
         &path
-    } else if path.is_dir() && path.join("index.html").is_file() {
+    } else if index.is_file(&path.join("index.html")) && path.is_dir() {
         index_html = path.join("index.html");
         &index_html
     } else {
@@ -251,8 +514,10 @@ fn check_file_url(url: &Url, ctx: &CheckContext) -> Result<(), CheckError> {
     // The URL might contain a fragment. In that case we need a full GET
     // request to check if the fragment exists.
     match url.fragment() {
-        Some(fragment) => check_file_fragment(&path, expanded_path, fragment),
-        None => Ok(()),
+        Some(fragment) if !is_top_of_page(fragment) => {
+            check_file_fragment(&path, expanded_path, fragment, index)
+        }
+        _ => Ok(()),
     }
 }
 
@@ -260,6 +525,7 @@ fn check_file_fragment(
     path: &Path,
     expanded_path: &Path,
     fragment: &str,
+    index: &SiteIndex,
 ) -> Result<(), CheckError> {
     debug!(
         "Checking fragment {} of file {}.",
@@ -267,34 +533,91 @@ fn check_file_fragment(
         expanded_path.display()
     );
 
-    fn get_html(expanded_path: &Path) -> Result<String, CheckError> {
-        read_to_string(expanded_path).map_err(|err| {
-            CheckError::Io(Box::new(IoError::FileIo(
-                expanded_path.to_string_lossy().to_string(),
-                err,
-            )))
-        })
+    let page = index.load(expanded_path);
+    let mut page = page.as_ref().as_ref().map_err(Clone::clone)?;
+    let redirected;
+    if let Some(redirect) = &page.redirect {
+        redirected = index.load(redirect);
+        page = redirected.as_ref().as_ref().map_err(Clone::clone)?;
     }
+    is_fragment_available(&Link::File(path.to_path_buf()), fragment, &page.anchors)
+}
 
-    let fetch_html = || {
-        let html = get_html(expanded_path)?;
-        if let Some(redirect) = parse_redirect(&html) {
-            get_html(&expanded_path.parent().unwrap().join(redirect))
-        } else {
-            Ok(html)
+/// Check a link to `doc.rust-lang.org` or `docs.rs` against local documentation, if the page can be found there.
+///
+/// Errors refer to `url`, not to the local file it was checked against.
+fn check_local_docs(
+    url: &Url,
+    ctx: &CheckContext,
+    index: &SiteIndex,
+) -> Option<Result<(), CheckError>> {
+    let local = local_docs_url(url, ctx)?;
+    debug!("Checking {} against local documentation at {}", url, local);
+    let mut page = url.clone();
+    page.set_fragment(None);
+    Some(check_file_url(&local, ctx, index).map_err(|err| match err {
+        CheckError::File(_) => CheckError::Http(page),
+        CheckError::Fragment(_, fragment, missing) => {
+            CheckError::Fragment(Link::Http(page), fragment, missing)
+        }
+        err => err,
+    }))
+}
+
+/// The page `url` points to in [`CheckContext::std_docs`] or [`CheckContext::dependency_docs`].
+///
+/// Returns `None` if `url` is not on `doc.rust-lang.org` or `docs.rs`, or if the crate or book it points into
+/// isn't documented locally.
+fn local_docs_url(url: &Url, ctx: &CheckContext) -> Option<Url> {
+    static RUST_VERSION: Lazy<Regex> = Lazy::new(|| Regex::new(r"^1\.[0-9]+(\.[0-9]+)?$").unwrap());
+
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let (root, path) = match url.host_str()? {
+        "doc.rust-lang.org" => {
+            // Links may point to a specific release channel or version, e.g. `/stable/std/` or `/1.50.0/std/`.
+            let path = match segments.split_first() {
+                Some((&first, rest))
+                    if ["stable", "beta", "nightly"].contains(&first)
+                        || RUST_VERSION.is_match(first) =>
+                {
+                    rest.join("/")
+                }
+                _ => segments.join("/"),
+            };
+            (ctx.std_docs.as_ref()?, path)
         }
+        "docs.rs" => {
+            // `/<crate>/<version>/<crate_name>/...`; the version is ignored.
+            // `/crate/<crate>/...` are pages about the crate itself, which rustdoc doesn't generate.
+            let path = match segments.as_slice() {
+                [krate, ..] if krate.is_empty() || *krate == "crate" => return None,
+                [_, _, rest @ ..] if !rest.is_empty() && !rest[0].is_empty() => rest.join("/"),
+                [krate, ..] => format!("{}/", krate.replace('-', "_")),
+                [] => return None,
+            };
+            (ctx.dependency_docs.as_ref()?, path)
+        }
+        _ => return None,
     };
-    is_fragment_available(&Link::File(path.to_path_buf()), fragment, fetch_html)
+    let first = path.split('/').next().unwrap();
+    if first.is_empty() || !root.join(first).is_dir() {
+        return None;
+    }
+    let mut local = Url::from_directory_path(root).ok()?.join(&path).ok()?;
+    local.set_fragment(url.fragment());
+    Some(local)
 }
 
-/// Check a URL with "http" or "https" scheme for availability. Returns `Err` if it is unavailable.
-fn check_http_url(url: &Url, ctx: &CheckContext) -> Result<(), CheckError> {
+/// Check whether a URL with "http" or "https" scheme is skipped or forbidden.
+///
+/// Returns `None` if the URL has to be fetched.
+fn check_http_url_offline(url: &Url, ctx: &CheckContext) -> Option<Result<(), CheckError>> {
     if ctx.check_http == HttpCheck::Ignored {
         warn!(
             "Skip checking {} as checking of http URLs is turned off",
             url
         );
-        return Ok(());
+        return Some(Ok(()));
     }
 
     for blacklisted_prefix in PREFIX_BLACKLIST.iter() {
@@ -303,70 +626,217 @@ fn check_http_url(url: &Url, ctx: &CheckContext) -> Result<(), CheckError> {
                 "Skip checking {} as URL prefix is on the builtin blacklist",
                 url
             );
-            return Ok(());
+            return Some(Ok(()));
         }
     }
 
     if ctx.check_http == HttpCheck::Forbidden {
-        return Err(CheckError::HttpForbidden(url.clone()));
+        return Some(Err(CheckError::HttpForbidden(url.clone())));
     }
+    None
+}
 
+/// Check a URL with "http" or "https" scheme for availability. Returns `Err` if it is unavailable.
+///
+/// This always sends a request (unless the result is cached); call [`is_available_offline`] first.
+pub(crate) fn fetch_http_url(
+    url: &Url,
+    ctx: &CheckContext,
+    client: &HttpClient,
+) -> Result<(), CheckError> {
     // The URL might contain a fragment. In that case we need a full GET
     // request to check if the fragment exists.
     if url.fragment().is_none() || !ctx.check_fragments {
+        let cache = ctx.http_cache.as_deref();
+        if let Some(cached) = cache.and_then(|cache| cache.get(url)) {
+            return if cached.is_success() {
+                check_redirects(url, &cached.redirects, ctx)
+            } else {
+                Err(CheckError::Io(Box::new(IoError::unexpected_status(
+                    url, &cached,
+                ))))
+            };
+        }
+
         info!("Check URL {url}");
-        match ureq::head(url.as_str()).call() {
+        let fetched = match client.head(url) {
             Err(ureq::Error::Status(405, _)) => {
                 // If HEAD isn't allowed, try sending a GET instead
-                ureq::get(url.as_str()).call()?;
-                Ok(())
+                client.get(url)
             }
-            Err(other) => Err(other.into()),
-            Ok(_) => Ok(()),
+            other => other,
+        };
+        if let Some(cache) = cache {
+            match &fetched {
+                Ok(Fetched {
+                    response,
+                    redirects,
+                    too_many_redirects: false,
+                }) => cache.insert(
+                    url,
+                    response.status(),
+                    response.status_text(),
+                    None,
+                    redirects,
+                ),
+                // Don't remember errors which might go away by the next run.
+                Err(ureq::Error::Status(status, resp)) if !is_transient_status(*status) => {
+                    cache.insert(url, *status, resp.status_text(), None, &[])
+                }
+                _ => {}
+            }
+        }
+        let fetched = fetched?;
+        if fetched.too_many_redirects {
+            return Err(CheckError::TooManyRedirects(url.clone(), fetched.redirects));
         }
+        check_redirects(url, &fetched.redirects, ctx)
     } else {
         // the URL might contain a fragment, in that case we need to check if
         // the fragment exists, this issues a GET request
-        check_http_fragment(url, url.fragment().unwrap())
+        check_http_fragment(url, url.fragment().unwrap(), ctx, client)
     }
 }
 
-fn check_http_fragment(url: &Url, fragment: &str) -> Result<(), CheckError> {
+fn check_http_fragment(
+    url: &Url,
+    fragment: &str,
+    ctx: &CheckContext,
+    client: &HttpClient,
+) -> Result<(), CheckError> {
+    if is_top_of_page(fragment) {
+        return Ok(());
+    }
     info!("Checking fragment {} of URL {}.", fragment, url.as_str());
 
-    fn get_html(url: &Url) -> Result<String, CheckError> {
-        let resp = ureq::get(url.as_str()).call()?;
-        Ok(resp.into_string().unwrap())
-    }
+    let cache = ctx.http_cache.as_deref();
 
-    let fetch_html = || {
-        let html = get_html(url)?;
+    let get = |url: &Url| {
+        let fetched = client.get(url).map_err(|err| {
+            if let (Some(cache), ureq::Error::Status(status, resp)) = (cache, &err) {
+                if !is_transient_status(*status) {
+                    cache.insert(url, *status, resp.status_text(), None, &[]);
+                }
+            }
+            CheckError::from(err)
+        })?;
+        if fetched.too_many_redirects {
+            return Err(CheckError::TooManyRedirects(url.clone(), fetched.redirects));
+        }
+        Ok(fetched)
+    };
+
+    let fetch_page = || {
+        if let Some(cached) = cache.and_then(|cache| cache.get(url)) {
+            if !cached.is_success() {
+                return Err(CheckError::Io(Box::new(IoError::unexpected_status(
+                    url, &cached,
+                ))));
+            } else if let Some(fragments) = cached.fragments {
+                return Ok(Page {
+                    fragments,
+                    redirects: cached.redirects,
+                });
+            }
+            // Otherwise, this was only checked with a HEAD request; fetch the whole page.
+        }
+
+        let Fetched {
+            response,
+            redirects,
+            ..
+        } = get(url)?;
+        let (status, status_text) = (response.status(), response.status_text().to_owned());
         // NOTE: only handles one level of nesting. Maybe we should have multiple levels?
-        let redirect = parse_redirect(&html).and_then(|s| {
-            Url::parse(&s)
+        let page_error = |err: PageError| {
+            let mut page = url.clone();
+            page.set_fragment(None);
+            CheckError::parse(Link::Http(page), &err)
+        };
+        let page = extract_page(response.into_reader(), None, false).map_err(page_error)?;
+        let redirect = page.redirect.as_ref().and_then(|s| {
+            Url::parse(s)
                 .map_err(|err| {
                     warn!("failed to parse Rustdoc redirect: {}", err);
                 })
                 .ok()
         });
-        if let Some(redirect) = redirect {
-            get_html(&redirect)
+        let page = if let Some(redirect) = redirect {
+            extract_page(get(&redirect)?.response.into_reader(), None, false).map_err(page_error)?
         } else {
-            Ok(html)
+            page
+        };
+
+        let fragments = page.anchors;
+        if let Some(cache) = cache {
+            cache.insert(
+                url,
+                status,
+                &status_text,
+                Some(fragments.clone()),
+                &redirects,
+            );
         }
+        Ok(Page {
+            fragments,
+            redirects,
+        })
     };
 
-    is_fragment_available(&Link::Http(url.clone()), fragment, fetch_html)?;
-    Ok(())
+    let link = Link::Http(url.clone());
+    let Page {
+        fragments,
+        redirects,
+    } = page_from(&link, fetch_page)?;
+    is_fragment_available(&link, fragment, &fragments)?;
+    check_redirects(url, &redirects, ctx)
+}
+
+/// Report the redirects that were followed to reach `url`, if they are worth reporting.
+fn check_redirects(
+    url: &Url,
+    redirects: &[Redirect],
+    ctx: &CheckContext,
+) -> Result<(), CheckError> {
+    if redirects.is_empty() {
+        return Ok(());
+    }
+    debug!(
+        "{} redirected to {}",
+        url,
+        redirects
+            .iter()
+            .map(|redirect| format!("{} ({})", redirect.location, redirect.status))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    );
+    if ctx.deny_cross_host_redirects
+        && redirects
+            .iter()
+            .any(|redirect| redirect.location.host_str() != url.host_str())
+    {
+        Err(CheckError::CrossHostRedirect(
+            url.clone(),
+            redirects.to_vec(),
+        ))
+    } else if redirects[0].is_permanent() {
+        Err(CheckError::PermanentRedirect(
+            url.clone(),
+            redirects.to_vec(),
+        ))
+    } else {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::HttpCheck;
+    use crate::{HttpCache, HttpCheck, SiteIndex};
 
-    use super::{check_file_url, is_available, CheckContext, CheckError, Link};
+    use super::{check_file_url, is_available, local_docs_url, CheckContext, CheckError, Link};
     use mockito::{self, mock};
     use std::env;
+    use std::sync::Arc;
     use url::Url;
 
     fn url_for(path: &str) -> Url {
@@ -388,7 +858,11 @@ mod test {
     }
 
     fn test_check_file_url(path: &str) -> Result<(), CheckError> {
-        check_file_url(&url_for(path), &CheckContext::default())
+        check_file_url(
+            &url_for(path),
+            &CheckContext::default(),
+            &SiteIndex::default(),
+        )
     }
 
     #[test]
@@ -558,6 +1032,31 @@ mod test {
         root.assert();
     }
 
+    #[test]
+    fn test_http_cache() {
+        let head = mock("HEAD", "/test_http_cache")
+            .with_status(404)
+            .expect(1)
+            .create();
+
+        let ctx = CheckContext {
+            check_http: HttpCheck::Enabled,
+            http_cache: Some(Arc::new(HttpCache::new("unused.json"))),
+            ..CheckContext::default()
+        };
+        let missing = Url::parse(&(mockito::server_url() + "/test_http_cache")).unwrap();
+        for _ in 0..2 {
+            match is_available(&missing, &ctx) {
+                Err(CheckError::Io(err)) => assert_eq!(
+                    err.to_string(),
+                    format!("Unexpected HTTP status fetching {}: Not Found", missing)
+                ),
+                x => panic!("Expected to report a 404, got {:?}", x),
+            }
+        }
+        head.assert();
+    }
+
     #[test]
     fn test_disabling_fragment_checks_file() {
         check_file_url(
@@ -566,6 +1065,7 @@ mod test {
                 check_fragments: false,
                 ..CheckContext::default()
             },
+            &SiteIndex::default(),
         )
         .unwrap();
     }
@@ -591,4 +1091,164 @@ mod test {
 
         root.assert();
     }
+
+    #[test]
+    fn test_redirects() {
+        let server = mockito::server_url();
+        let _moved = mock("HEAD", "/test_redirects/moved")
+            .with_status(301)
+            .with_header("location", "/test_redirects/new")
+            .create();
+        let _found = mock("HEAD", "/test_redirects/found")
+            .with_status(302)
+            .with_header("location", &(server.clone() + "/test_redirects/new"))
+            .create();
+        let _new = mock("HEAD", "/test_redirects/new")
+            .with_status(200)
+            .create();
+        let _loop = mock("HEAD", "/test_redirects/loop")
+            .with_status(307)
+            .with_header("location", "/test_redirects/loop")
+            .create();
+        let _other_host = mock("HEAD", "/test_redirects/other-host")
+            .with_status(302)
+            .with_header("location", &server.replace("127.0.0.1", "localhost"))
+            .create();
+        let _other_root = mock("HEAD", "/").with_status(200).create();
+
+        let ctx = CheckContext {
+            check_http: HttpCheck::Enabled,
+            ..CheckContext::default()
+        };
+        let url = |path: &str| Url::parse(&format!("{}/test_redirects/{}", server, path)).unwrap();
+
+        match is_available(&url("moved"), &ctx) {
+            Err(err @ CheckError::PermanentRedirect(..)) => {
+                assert_eq!(
+                    err.to_string(),
+                    format!(
+                        "Linked URL {} permanently redirects to {}",
+                        url("moved"),
+                        url("new")
+                    )
+                );
+                assert_eq!(err.redirects().unwrap()[0].status, 301);
+            }
+            x => panic!("Expected a permanent redirect, got {:?}", x),
+        }
+        is_available(&url("found"), &ctx).unwrap();
+        match is_available(&url("loop"), &ctx) {
+            Err(CheckError::TooManyRedirects(_, redirects)) => assert_eq!(redirects.len(), 5),
+            x => panic!("Expected too many redirects, got {:?}", x),
+        }
+
+        is_available(&url("other-host"), &ctx).unwrap();
+        let deny = CheckContext {
+            deny_cross_host_redirects: true,
+            ..ctx
+        };
+        match is_available(&url("other-host"), &deny) {
+            Err(CheckError::CrossHostRedirect(..)) => {}
+            x => panic!("Expected a cross-host redirect, got {:?}", x),
+        }
+        is_available(&url("found"), &deny).unwrap();
+    }
+
+    #[test]
+    fn test_redirected_http_fragment() {
+        let _moved = mock("GET", "/test_redirected_http_fragment")
+            .with_status(308)
+            .with_header("location", "/test_redirected_http_fragment/new")
+            .create();
+        let _new = mock("GET", "/test_redirected_http_fragment/new")
+            .with_status(200)
+            .with_header("content-type", "text/html")
+            .with_body(r#"<p id="here"></p>"#)
+            .create();
+
+        let ctx = CheckContext {
+            check_http: HttpCheck::Enabled,
+            ..CheckContext::default()
+        };
+        let url = mockito::server_url() + "/test_redirected_http_fragment";
+        match is_available(&Url::parse(&(url.clone() + "#here")).unwrap(), &ctx) {
+            Err(CheckError::PermanentRedirect(..)) => {}
+            x => panic!("Expected a permanent redirect, got {:?}", x),
+        }
+        match is_available(&Url::parse(&(url + "#missing")).unwrap(), &ctx) {
+            Err(CheckError::Fragment(..)) => {}
+            x => panic!("Expected a missing fragment, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_local_docs_url() {
+        // `tests/html` stands in for both the documentation of `std` and of a dependency.
+        let ctx = CheckContext {
+            std_docs: Some(env::current_dir().unwrap().join("tests")),
+            dependency_docs: Some(env::current_dir().unwrap().join("tests")),
+            ..CheckContext::default()
+        };
+        let local = |url: &str| local_docs_url(&Url::parse(url).unwrap(), &ctx);
+
+        for url in &[
+            "https://doc.rust-lang.org/html/anchors.html#h1",
+            "https://doc.rust-lang.org/stable/html/anchors.html#h1",
+            "https://doc.rust-lang.org/1.50.0/html/anchors.html#h1",
+            "https://docs.rs/html/0.1.0/html/anchors.html#h1",
+            "https://docs.rs/html/latest/html/anchors.html#h1",
+        ] {
+            assert_eq!(local(url), Some(url_for("tests/html/anchors.html#h1")));
+        }
+        assert_eq!(
+            local("https://docs.rs/html/0.1.0/"),
+            Some(url_for("tests/html/"))
+        );
+        assert_eq!(local("https://docs.rs/html"), Some(url_for("tests/html/")));
+
+        // Not documented locally
+        assert_eq!(local("https://doc.rust-lang.org/nightly/proc_macro/"), None);
+        assert_eq!(local("https://docs.rs/serde/1.0.0/serde/"), None);
+        assert_eq!(local("https://docs.rs/crate/html/0.1.0"), None);
+        assert_eq!(local("https://doc.rust-lang.org/"), None);
+        assert_eq!(local("https://example.com/html/anchors.html"), None);
+    }
+
+    #[test]
+    fn test_local_docs() {
+        let ctx = CheckContext {
+            std_docs: Some(env::current_dir().unwrap().join("tests")),
+            ..CheckContext::default()
+        };
+        is_available(
+            &Url::parse("https://doc.rust-lang.org/stable/html/anchors.html#h1").unwrap(),
+            &ctx,
+        )
+        .unwrap();
+        match is_available(
+            &Url::parse("https://doc.rust-lang.org/stable/html/typo.html").unwrap(),
+            &ctx,
+        ) {
+            Err(CheckError::Http(url)) => {
+                assert_eq!(
+                    url.as_str(),
+                    "https://doc.rust-lang.org/stable/html/typo.html"
+                )
+            }
+            x => panic!("Expected a missing page, got {:?}", x),
+        }
+        match is_available(
+            &Url::parse("https://doc.rust-lang.org/stable/html/anchors.html#typo").unwrap(),
+            &ctx,
+        ) {
+            Err(CheckError::Fragment(Link::Http(url), fragment, None)) => {
+                assert_eq!(
+                    url.as_str(),
+                    "https://doc.rust-lang.org/stable/html/anchors.html"
+                );
+                assert_eq!(fragment, "typo");
+            }
+            x => panic!("Expected a missing fragment, got {:?}", x),
+        }
+    }
 }
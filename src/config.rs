@@ -0,0 +1,495 @@
+//! Settings read from `deadlinks.toml` and Cargo metadata.
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::de::{Deserialize, Deserializer, Error as _};
+use serde_derive::Deserialize;
+use url::Url;
+
+use crate::{CheckContext, HttpCheck, HttpHeader, IgnoreList, IgnorePattern, LinkKind};
+
+/// The name of the configuration file looked for at the root of a workspace.
+pub const CONFIG_FILE_NAME: &str = "deadlinks.toml";
+
+/// Settings for deadlinks, as read from a configuration file or the command line.
+///
+/// Every field is optional; unset fields fall back to the next layer of configuration,
+/// and finally to the defaults of [`CheckContext`]. In TOML, the keys are the same as the
+/// command line flags, e.g.:
+///
+/// ```toml
+/// http = "enabled"   # or "ignored", or "forbidden"
+/// check-fragments = true
+/// check-intra-doc-links = true
+/// link-kinds = ["hyperlink", "media"]
+/// http-cache = "target/deadlinks/http-cache.json"
+/// http-cache-ttl = "7d"
+/// http-cache-failure-ttl = "1h"
+/// max-http-requests = 16
+/// max-http-requests-per-host = 4
+/// http-rate-limit = 2.5  # requests per second to each host
+/// http-retries = 2
+/// http-retry-delay = "1s"
+/// deny-transient-errors = false
+/// http-connect-timeout = "10s"
+/// http-read-timeout = "30s"
+/// user-agent = "my-docs-checker"
+/// http-proxy = "http://proxy.example.com:8080"
+/// http-ca-bundle = "certs/internal-ca.pem"
+/// max-redirects = 5
+/// deny-cross-host-redirects = false
+/// local-docs = true
+/// base-url = "https://example.com/docs/"
+/// site-root = "/docs/"
+/// http-headers = [
+///     { urls = "https://gitlab.example.com/**", name = "PRIVATE-TOKEN", value = "${GITLAB_TOKEN}" },
+/// ]
+/// ignore-links = ["https://www.linkedin.com/**", "regex:^https://example\\.com/login"]
+/// ignore-files = ["src/**"]
+/// orphan-pages = true
+/// entry-points = ["404.html"]
+/// ```
+///
+/// Unlike other settings, `ignore-links`, `ignore-files`, `http-headers` and `entry-points` are combined with the patterns from other
+/// layers instead of replacing them.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub http: Option<HttpCheck>,
+    pub check_fragments: Option<bool>,
+    pub check_intra_doc_links: Option<bool>,
+    pub link_kinds: Option<HashSet<LinkKind>>,
+    /// Relative paths are resolved relative to the file the setting was read from.
+    pub http_cache: Option<PathBuf>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub http_cache_ttl: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub http_cache_failure_ttl: Option<Duration>,
+    /// See [`HttpLimits::max_concurrent`](crate::HttpLimits::max_concurrent).
+    pub max_http_requests: Option<usize>,
+    /// See [`HttpLimits::max_concurrent_per_host`](crate::HttpLimits::max_concurrent_per_host).
+    pub max_http_requests_per_host: Option<usize>,
+    /// See [`HttpLimits::requests_per_second`](crate::HttpLimits::requests_per_second).
+    pub http_rate_limit: Option<f64>,
+    /// See [`HttpLimits::retries`](crate::HttpLimits::retries).
+    pub http_retries: Option<u32>,
+    /// See [`HttpLimits::retry_delay`](crate::HttpLimits::retry_delay).
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub http_retry_delay: Option<Duration>,
+    /// See [`CheckContext::deny_transient_errors`].
+    pub deny_transient_errors: Option<bool>,
+    /// See [`HttpClientConfig::connect_timeout`](crate::HttpClientConfig::connect_timeout).
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub http_connect_timeout: Option<Duration>,
+    /// See [`HttpClientConfig::read_timeout`](crate::HttpClientConfig::read_timeout).
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub http_read_timeout: Option<Duration>,
+    /// See [`HttpClientConfig::user_agent`](crate::HttpClientConfig::user_agent).
+    pub user_agent: Option<String>,
+    /// See [`HttpClientConfig::proxy`](crate::HttpClientConfig::proxy).
+    pub http_proxy: Option<String>,
+    /// See [`HttpClientConfig::ca_bundle`](crate::HttpClientConfig::ca_bundle). Relative paths are resolved like `http-cache`.
+    pub http_ca_bundle: Option<PathBuf>,
+    /// See [`HttpClientConfig::max_redirects`](crate::HttpClientConfig::max_redirects).
+    pub max_redirects: Option<u32>,
+    /// See [`CheckContext::deny_cross_host_redirects`].
+    pub deny_cross_host_redirects: Option<bool>,
+    /// See [`HttpClientConfig::headers`](crate::HttpClientConfig::headers).
+    #[serde(default)]
+    pub http_headers: Vec<HttpHeader>,
+    /// Check links to doc.rust-lang.org and docs.rs against local documentation;
+    /// see [`CheckContext::std_docs`] and [`CheckContext::dependency_docs`].
+    pub local_docs: Option<bool>,
+    /// See [`CheckContext::site_root`].
+    pub site_root: Option<String>,
+    /// See [`CheckContext::base_url`].
+    pub base_url: Option<Url>,
+    /// See [`CheckContext::ignore_links`].
+    #[serde(default)]
+    pub ignore_links: Vec<IgnorePattern>,
+    /// See [`CheckContext::ignore_files`].
+    #[serde(default)]
+    pub ignore_files: Vec<IgnorePattern>,
+    /// See [`CheckContext::check_orphan_pages`].
+    pub orphan_pages: Option<bool>,
+    /// See [`CheckContext::entry_points`].
+    #[serde(default)]
+    pub entry_points: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read
+    Io(PathBuf, io::Error),
+    /// The configuration was not valid. The first field describes where it came from.
+    Invalid(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Invalid(source, err) => {
+                write!(f, "invalid configuration in {}: {}", source, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Read the configuration from a TOML file.
+    ///
+    /// Relative paths in the file are resolved relative to the directory containing it.
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_owned(), err))?;
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|err| ConfigError::Invalid(path.display().to_string(), err.to_string()))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    /// Read `deadlinks.toml` from `dir`, if it exists.
+    pub fn from_dir(dir: &Path) -> Result<Option<Config>, ConfigError> {
+        let path = dir.join(CONFIG_FILE_NAME);
+        if path.is_file() {
+            Self::from_file(&path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the `deadlinks` key of `[package.metadata]` or `[workspace.metadata]` in `Cargo.toml`.
+    ///
+    /// `metadata` is the whole metadata table, as given by `cargo metadata`.
+    /// `manifest_dir` is the directory containing `Cargo.toml`, used to resolve relative paths.
+    pub fn from_cargo_metadata(
+        metadata: &serde_json::Value,
+        manifest_dir: &Path,
+    ) -> Result<Option<Config>, ConfigError> {
+        let value = match metadata.get("deadlinks") {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut config = Config::deserialize(value).map_err(|err| {
+            let manifest = manifest_dir.join("Cargo.toml");
+            ConfigError::Invalid(manifest.display().to_string(), err.to_string())
+        })?;
+        config.resolve_paths(manifest_dir);
+        Ok(Some(config))
+    }
+
+    /// Read the `[output.deadlinks]` table of an mdBook's `book.toml`.
+    ///
+    /// The keys mdBook itself reads from the tables of renderers (`command` and `optional`) are ignored.
+    /// `book_root` is the directory containing `book.toml`, used to resolve relative paths.
+    pub fn from_book(table: &serde_json::Value, book_root: &Path) -> Result<Config, ConfigError> {
+        let mut table = table.clone();
+        if let Some(table) = table.as_object_mut() {
+            table.remove("command");
+            table.remove("optional");
+        }
+        let mut config = Config::deserialize(&table).map_err(|err| {
+            let book_toml = book_root.join("book.toml");
+            ConfigError::Invalid(book_toml.display().to_string(), err.to_string())
+        })?;
+        config.resolve_paths(book_root);
+        Ok(config)
+    }
+
+    /// Combine two configurations, preferring the settings in `overrides`.
+    pub fn merge(mut self, overrides: Config) -> Config {
+        self.ignore_links.extend(overrides.ignore_links);
+        self.ignore_files.extend(overrides.ignore_files);
+        self.http_headers.extend(overrides.http_headers);
+        self.entry_points.extend(overrides.entry_points);
+        Config {
+            http: overrides.http.or(self.http),
+            check_fragments: overrides.check_fragments.or(self.check_fragments),
+            check_intra_doc_links: overrides
+                .check_intra_doc_links
+                .or(self.check_intra_doc_links),
+            link_kinds: overrides.link_kinds.or(self.link_kinds),
+            http_cache: overrides.http_cache.or(self.http_cache),
+            http_cache_ttl: overrides.http_cache_ttl.or(self.http_cache_ttl),
+            http_cache_failure_ttl: overrides
+                .http_cache_failure_ttl
+                .or(self.http_cache_failure_ttl),
+            max_http_requests: overrides.max_http_requests.or(self.max_http_requests),
+            max_http_requests_per_host: overrides
+                .max_http_requests_per_host
+                .or(self.max_http_requests_per_host),
+            http_rate_limit: overrides.http_rate_limit.or(self.http_rate_limit),
+            http_retries: overrides.http_retries.or(self.http_retries),
+            http_retry_delay: overrides.http_retry_delay.or(self.http_retry_delay),
+            deny_transient_errors: overrides
+                .deny_transient_errors
+                .or(self.deny_transient_errors),
+            http_connect_timeout: overrides.http_connect_timeout.or(self.http_connect_timeout),
+            http_read_timeout: overrides.http_read_timeout.or(self.http_read_timeout),
+            user_agent: overrides.user_agent.or(self.user_agent),
+            http_proxy: overrides.http_proxy.or(self.http_proxy),
+            http_ca_bundle: overrides.http_ca_bundle.or(self.http_ca_bundle),
+            max_redirects: overrides.max_redirects.or(self.max_redirects),
+            deny_cross_host_redirects: overrides
+                .deny_cross_host_redirects
+                .or(self.deny_cross_host_redirects),
+            local_docs: overrides.local_docs.or(self.local_docs),
+            site_root: overrides.site_root.or(self.site_root),
+            base_url: overrides.base_url.or(self.base_url),
+            http_headers: self.http_headers,
+            ignore_links: self.ignore_links,
+            ignore_files: self.ignore_files,
+            orphan_pages: overrides.orphan_pages.or(self.orphan_pages),
+            entry_points: self.entry_points,
+        }
+    }
+
+    /// Apply these settings on top of an existing `CheckContext`.
+    ///
+    /// The HTTP cache settings are not applied, since loading the cache can fail,
+    /// and neither is `local-docs`, since the documentation has to be found first.
+    pub fn apply(&self, ctx: &mut CheckContext) {
+        if let Some(http) = self.http {
+            ctx.check_http = http;
+        }
+        if let Some(check_fragments) = self.check_fragments {
+            ctx.check_fragments = check_fragments;
+        }
+        if let Some(check_intra_doc_links) = self.check_intra_doc_links {
+            ctx.check_intra_doc_links = check_intra_doc_links;
+        }
+        if let Some(link_kinds) = &self.link_kinds {
+            ctx.link_kinds = link_kinds.clone();
+        }
+        if let Some(max) = self.max_http_requests {
+            ctx.http_limits.max_concurrent = max;
+        }
+        if let Some(max) = self.max_http_requests_per_host {
+            ctx.http_limits.max_concurrent_per_host = max;
+        }
+        if let Some(rate) = self.http_rate_limit {
+            ctx.http_limits.requests_per_second = Some(rate);
+        }
+        if let Some(retries) = self.http_retries {
+            ctx.http_limits.retries = retries;
+        }
+        if let Some(delay) = self.http_retry_delay {
+            ctx.http_limits.retry_delay = delay;
+        }
+        if let Some(deny) = self.deny_transient_errors {
+            ctx.deny_transient_errors = deny;
+        }
+        if let Some(timeout) = self.http_connect_timeout {
+            ctx.http_client.connect_timeout = timeout;
+        }
+        if let Some(timeout) = self.http_read_timeout {
+            ctx.http_client.read_timeout = timeout;
+        }
+        if let Some(user_agent) = &self.user_agent {
+            ctx.http_client.user_agent = user_agent.clone();
+        }
+        if let Some(proxy) = &self.http_proxy {
+            ctx.http_client.proxy = Some(proxy.clone());
+        }
+        if let Some(ca_bundle) = &self.http_ca_bundle {
+            ctx.http_client.ca_bundle = Some(ca_bundle.clone());
+        }
+        if let Some(max) = self.max_redirects {
+            ctx.http_client.max_redirects = max;
+        }
+        if let Some(deny) = self.deny_cross_host_redirects {
+            ctx.deny_cross_host_redirects = deny;
+        }
+        if let Some(site_root) = &self.site_root {
+            ctx.site_root = Some(format!("/{}", site_root.trim_start_matches('/')));
+        }
+        if let Some(base_url) = &self.base_url {
+            ctx.base_url = Some(base_url.clone());
+        }
+        ctx.http_client
+            .headers
+            .extend(self.http_headers.iter().cloned());
+        if !self.ignore_links.is_empty() {
+            ctx.ignore_links = Arc::new(IgnoreList::new(self.ignore_links.iter().cloned()));
+        }
+        if !self.ignore_files.is_empty() {
+            ctx.ignore_files = Arc::new(IgnoreList::new(self.ignore_files.iter().cloned()));
+        }
+        if let Some(orphan_pages) = self.orphan_pages {
+            ctx.check_orphan_pages = orphan_pages;
+        }
+        ctx.entry_points.extend(self.entry_points.iter().cloned());
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        for path in self.http_cache.iter_mut().chain(&mut self.http_ca_bundle) {
+            *path = base.join(&*path);
+        }
+    }
+}
+
+/// Parses a duration like `90s`, `30m`, `12h` or `7d`. A number without a unit is in seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => duration.split_at(i),
+        None => (duration, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", duration))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "unknown unit '{}' in duration '{}'",
+                unit, duration
+            ))
+        }
+    };
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}'", duration))
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let duration = String::deserialize(deserializer)?;
+    parse_duration(&duration)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use super::{parse_duration, Config};
+    use crate::{CheckContext, HttpCheck, LinkKind};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            parse_duration("2d"),
+            Ok(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_toml_config() {
+        let config: Config = toml::from_str(
+            r#"
+            http = "forbidden"
+            check-intra-doc-links = true
+            link-kinds = ["media"]
+            http-cache-ttl = "12h"
+            max-http-requests-per-host = 1
+            http-rate-limit = 0.5
+            http-retries = 0
+            http-retry-delay = "5s"
+            deny-transient-errors = true
+            user-agent = "my-agent"
+            max-redirects = 1
+            http-read-timeout = "1m"
+            http-headers = [{ urls = "https://example.com/**", name = "X-Token", value = "abc" }]
+            base-url = "https://example.com/docs/"
+            orphan-pages = true
+            entry-points = ["404.html"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.http, Some(HttpCheck::Forbidden));
+        assert_eq!(config.check_fragments, None);
+        assert_eq!(
+            config.http_cache_ttl,
+            Some(Duration::from_secs(12 * 60 * 60))
+        );
+
+        let mut ctx = CheckContext::default();
+        config.apply(&mut ctx);
+        assert_eq!(ctx.check_http, HttpCheck::Forbidden);
+        assert!(ctx.check_fragments);
+        assert!(ctx.check_intra_doc_links);
+        assert_eq!(ctx.link_kinds.len(), 1);
+        assert!(ctx.link_kinds.contains(&LinkKind::Media));
+        assert_eq!(ctx.http_limits.max_concurrent, 16);
+        assert_eq!(ctx.http_limits.max_concurrent_per_host, 1);
+        assert_eq!(ctx.http_limits.requests_per_second, Some(0.5));
+        assert_eq!(ctx.http_limits.retries, 0);
+        assert_eq!(ctx.http_limits.retry_delay, Duration::from_secs(5));
+        assert!(ctx.deny_transient_errors);
+        assert_eq!(ctx.http_client.user_agent, "my-agent");
+        assert_eq!(ctx.http_client.max_redirects, 1);
+        assert!(!ctx.deny_cross_host_redirects);
+        assert_eq!(ctx.http_client.read_timeout, Duration::from_secs(60));
+        assert_eq!(ctx.http_client.headers[0].value, "abc");
+        assert_eq!(ctx.base_url.unwrap().as_str(), "https://example.com/docs/");
+        assert_eq!(ctx.site_root, None);
+        assert!(ctx.check_orphan_pages);
+        assert_eq!(ctx.entry_points, ["404.html"]);
+
+        assert!(toml::from_str::<Config>("check-htp = true").is_err());
+        assert!(toml::from_str::<Config>(r#"ignore-links = ["regex:("]"#).is_err());
+        assert!(toml::from_str::<Config>(r#"http-headers = [{ urls = "**" }]"#).is_err());
+        assert!(toml::from_str::<Config>(r#"base-url = "/docs/""#).is_err());
+    }
+
+    #[test]
+    fn test_cargo_metadata_config() {
+        let metadata = serde_json::json!({
+            "deadlinks": { "http": "enabled", "http-cache": "cache.json" },
+            "other-tool": {},
+        });
+        let config = Config::from_cargo_metadata(&metadata, Path::new("/ws"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.http, Some(HttpCheck::Enabled));
+        assert_eq!(config.http_cache, Some(PathBuf::from("/ws/cache.json")));
+
+        let none = Config::from_cargo_metadata(&serde_json::Value::Null, Path::new("/ws"));
+        assert!(none.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_merge() {
+        let file = Config {
+            http: Some(HttpCheck::Enabled),
+            check_fragments: Some(false),
+            ignore_links: vec!["https://example.com/**".parse().unwrap()],
+            orphan_pages: Some(true),
+            ..Config::default()
+        };
+        let cli = Config {
+            http: Some(HttpCheck::Forbidden),
+            orphan_pages: Some(false),
+            ignore_links: vec!["regex:login".parse().unwrap()],
+            ..Config::default()
+        };
+        let merged = file.merge(cli);
+        assert_eq!(merged.http, Some(HttpCheck::Forbidden));
+        assert_eq!(merged.check_fragments, Some(false));
+        // Flags on the command line can turn settings off again.
+        assert_eq!(merged.orphan_pages, Some(false));
+        assert_eq!(merged.ignore_links.len(), 2);
+    }
+}
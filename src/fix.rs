@@ -0,0 +1,567 @@
+//! Rewriting broken links in the doc comments they came from.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use log::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::{CheckError, Report, SourceLocation};
+
+/// The prefixes rustdoc uses for the pages of each kind of item, e.g. `fn.foo.html`.
+const ITEM_KINDS: [&str; 14] = [
+    "attr",
+    "constant",
+    "derive",
+    "enum",
+    "fn",
+    "keyword",
+    "macro",
+    "primitive",
+    "static",
+    "struct",
+    "trait",
+    "traitalias",
+    "type",
+    "union",
+];
+
+/// A link target which should be replaced, as it would be written in a doc comment.
+///
+/// Fragments are not included, and are kept as they are when the link is rewritten.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LinkFix {
+    /// The line of the doc comment the broken link was written in, if it is known (see [`Origin`](crate::Origin)).
+    ///
+    /// Only this line is rewritten. Fixes without a source can only be found by searching for the link,
+    /// which might find the same text in places where it isn't broken, so they are never applied.
+    pub source: Option<SourceLocation>,
+    pub old: String,
+    pub new: String,
+}
+
+/// Suggest fixes for the errors in `report` which have an unambiguous replacement.
+///
+/// - Permanent redirects are replaced with the URL they redirect to.
+/// - Links to rustdoc pages which no longer exist are replaced with the page for an item with the same name,
+///   if there is exactly one, e.g. because the item was moved to a different module.
+///
+/// Each fix is for the doc comment the error was found in, if it is known. Links with conflicting suggestions,
+/// e.g. because the same relative link is broken in different ways on different pages, are not fixed.
+pub fn suggest_fixes(report: &Report) -> Vec<LinkFix> {
+    let mut items = HashMap::new();
+    let mut suggestions: BTreeMap<(Option<SourceLocation>, String), Option<String>> =
+        BTreeMap::new();
+
+    for (root, file, error) in report.errors() {
+        let source = error
+            .origin
+            .as_ref()
+            .and_then(|origin| origin.source.clone());
+        let mut suggest = |old: String, new: String| {
+            suggestions
+                .entry((source.clone(), old))
+                .and_modify(|existing| {
+                    if existing.as_ref() != Some(&new) {
+                        *existing = None;
+                    }
+                })
+                .or_insert_with(|| Some(new.clone()));
+        };
+        match &error.error {
+            CheckError::PermanentRedirect(url, redirects) => {
+                let mut old = url.clone();
+                old.set_fragment(None);
+                let mut new = redirects
+                    .iter()
+                    .take_while(|redirect| redirect.is_permanent())
+                    .last()
+                    .expect("permanent redirects are never empty")
+                    .location
+                    .clone();
+                new.set_fragment(None);
+                // `Url` always adds a trailing slash to bare hosts, but the link probably didn't have one.
+                if old.path() == "/" && old.query().is_none() {
+                    let old = old.as_str().trim_end_matches('/').to_owned();
+                    let new = new.as_str().trim_end_matches('/').to_owned();
+                    suggest(old, new);
+                }
+                suggest(old.into(), new.into());
+            }
+            CheckError::File(missing) => {
+                let page = root.join(&file.path);
+                let missing = root.join(missing);
+                let dir = match page.parent() {
+                    Some(dir) => dir,
+                    None => continue,
+                };
+                let name = match item_name(&missing) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let candidates = items
+                    .entry(root.to_path_buf())
+                    .or_insert_with(|| find_items(root))
+                    .get(name)
+                    .map_or(&[][..], Vec::as_slice);
+                if let [moved] = candidates {
+                    suggest(relative_link(&missing, dir), relative_link(moved, dir));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    suggestions
+        .into_iter()
+        .filter_map(|((source, old), new)| {
+            Some(LinkFix {
+                source,
+                old,
+                new: new?,
+            })
+        })
+        .collect()
+}
+
+/// The name of the item documented on a rustdoc page, e.g. `foo` for `fn.foo.html`.
+pub(crate) fn item_name(page: &Path) -> Option<&str> {
+    let file_name = page.file_name()?.to_str()?.strip_suffix(".html")?;
+    let dot = file_name.find('.')?;
+    if ITEM_KINDS.contains(&&file_name[..dot]) {
+        Some(&file_name[dot + 1..])
+    } else {
+        None
+    }
+}
+
+/// All item pages below `root`, by the name of the item.
+fn find_items(root: &Path) -> HashMap<String, Vec<PathBuf>> {
+    let mut items: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+        if let Some(name) = item_name(entry.path()) {
+            items
+                .entry(name.to_owned())
+                .or_default()
+                .push(entry.path().to_owned());
+        }
+    }
+    items
+}
+
+/// The relative link from a page in `dir` to `target`, with `/` as a separator.
+fn relative_link(target: &Path, dir: &Path) -> String {
+    let target: Vec<_> = target.components().collect();
+    let dir: Vec<_> = dir.components().collect();
+    let common = target.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+    let parents = dir[common..].iter().map(|_| "..".to_owned());
+    let rest = target[common..].iter().filter_map(|c| match c {
+        Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+        _ => None,
+    });
+    parents.chain(rest).collect::<Vec<_>>().join("/")
+}
+
+/// A single line of a source file which will be changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceEdit {
+    pub path: PathBuf,
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for SourceEdit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "--- {}", self.path.display())?;
+        writeln!(f, "+++ {}", self.path.display())?;
+        writeln!(f, "@@ -{} +{} @@", self.line, self.line)?;
+        writeln!(f, "-{}", self.old)?;
+        write!(f, "+{}", self.new)
+    }
+}
+
+/// Find the doc comment lines the links in `fixes` were written in, and how to change them.
+///
+/// Only fixes with a [`LinkFix::source`] are used; relative paths are relative to `source_root`.
+/// Only `///` and `//!` comments and `#[doc = "..."]` attributes are changed. Files which don't exist are skipped.
+pub fn find_edits(source_root: &Path, fixes: &[LinkFix]) -> io::Result<Vec<SourceEdit>> {
+    let mut by_line: BTreeMap<(PathBuf, usize), Vec<&LinkFix>> = BTreeMap::new();
+    for fix in fixes {
+        if let Some(source) = &fix.source {
+            let path = source_root.join(&source.path);
+            by_line.entry((path, source.line)).or_default().push(fix);
+        }
+    }
+
+    let mut edits = Vec::new();
+    let mut files = HashMap::new();
+    for ((path, line), fixes) in by_line {
+        if !files.contains_key(&path) {
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => Some(source),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    debug!("not fixing links in missing file {}", path.display());
+                    None
+                }
+                Err(err) => return Err(err),
+            };
+            files.insert(path.clone(), source);
+        }
+        let old = match files[&path]
+            .as_ref()
+            .and_then(|source| source.lines().nth(line.wrapping_sub(1)))
+        {
+            Some(old) if is_doc_line(old) => old,
+            _ => continue,
+        };
+        let new = fixes
+            .iter()
+            .fold(old.to_owned(), |line, fix| replace_link(&line, fix));
+        if new != old {
+            debug!("rewriting {}:{}", path.display(), line);
+            edits.push(SourceEdit {
+                old: old.to_owned(),
+                path,
+                line,
+                new,
+            });
+        }
+    }
+    Ok(edits)
+}
+
+/// Find every doc comment in the `.rs` files below `source_root` which contains one of the links in `fixes`
+/// without a [`LinkFix::source`].
+///
+/// These are only suggestions: the same text might be a working link elsewhere, e.g. relative to a different
+/// module, so they should be reviewed instead of applied. `target` directories and hidden directories are skipped.
+pub fn find_suggestions(source_root: &Path, fixes: &[LinkFix]) -> io::Result<Vec<SourceEdit>> {
+    let fixes: Vec<_> = fixes.iter().filter(|fix| fix.source.is_none()).collect();
+    let mut edits = Vec::new();
+    if fixes.is_empty() {
+        return Ok(edits);
+    }
+    let files = WalkDir::new(source_root)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.depth() == 0 || !(name == "target" || name.starts_with('.'))
+        });
+    for entry in files {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_file() || entry.path().extension() != Some("rs".as_ref()) {
+            continue;
+        }
+        let source = fs::read_to_string(entry.path())?;
+        for (i, line) in source.lines().enumerate() {
+            if !is_doc_line(line) {
+                continue;
+            }
+            let new = fixes
+                .iter()
+                .fold(line.to_owned(), |line, fix| replace_link(&line, fix));
+            if new != line {
+                debug!("suggesting a fix for {}:{}", entry.path().display(), i + 1);
+                edits.push(SourceEdit {
+                    path: entry.path().to_owned(),
+                    line: i + 1,
+                    old: line.to_owned(),
+                    new,
+                });
+            }
+        }
+    }
+    Ok(edits)
+}
+
+pub(crate) fn is_doc_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("///")
+        || line.starts_with("//!")
+        || line.starts_with("#[doc")
+        || line.starts_with("#![doc")
+}
+
+/// Replace each occurrence of `fix.old` in `line` which is a whole link target,
+/// e.g. in `[text](old)`, `<old>` or `[text]: old`, possibly followed by a fragment.
+fn replace_link(line: &str, fix: &LinkFix) -> String {
+    let is_start = |c: char| matches!(c, '(' | '<' | ':' | '"') || c.is_whitespace();
+    let is_end = |c: char| matches!(c, ')' | '>' | '#' | '"') || c.is_whitespace();
+
+    let mut replaced = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(&fix.old) {
+        let end = start + fix.old.len();
+        // Relative links may start with `./`, which is not part of the resolved path.
+        let prefix = &rest[..start];
+        let before = prefix
+            .strip_suffix("./")
+            .filter(|_| !fix.old.starts_with("../"))
+            .unwrap_or(prefix)
+            .chars()
+            .next_back();
+        let after = rest[end..].chars().next();
+        replaced.push_str(&rest[..start]);
+        let whole_link = match (before, after) {
+            (Some(before), Some(after)) => is_start(before) && is_end(after),
+            (Some(before), None) => is_start(before),
+            (None, _) => false,
+        };
+        if whole_link {
+            replaced.push_str(&fix.new);
+        } else {
+            replaced.push_str(&fix.old);
+        }
+        rest = &rest[end..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// Write `edits` to disk, returning how many lines were changed.
+///
+/// Edits whose line no longer exists or no longer matches [`SourceEdit::old`] (e.g. because the file was changed
+/// since the edits were found) are skipped with a warning.
+pub fn apply_edits(edits: &[SourceEdit]) -> io::Result<usize> {
+    let mut by_file: BTreeMap<&Path, Vec<&SourceEdit>> = BTreeMap::new();
+    for edit in edits {
+        by_file.entry(&edit.path).or_default().push(edit);
+    }
+    let mut changed = 0;
+    for (path, edits) in by_file {
+        let source = fs::read_to_string(path)?;
+        let mut lines: Vec<_> = source.split('\n').map(str::to_owned).collect();
+        let mut file_changed = false;
+        for edit in edits {
+            let line = match edit.line.checked_sub(1).and_then(|i| lines.get_mut(i)) {
+                Some(line) if line.trim_end_matches('\r') == edit.old => line,
+                _ => {
+                    warn!(
+                        "Not changing line {} of {}, since it no longer reads '{}'",
+                        edit.line,
+                        path.display(),
+                        edit.old
+                    );
+                    continue;
+                }
+            };
+            // Keep `\r` for files with Windows line endings.
+            let cr = if line.ends_with('\r') { "\r" } else { "" };
+            *line = format!("{}{}", edit.new, cr);
+            file_changed = true;
+            changed += 1;
+        }
+        if file_changed {
+            fs::write(path, lines.join("\n"))?;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use url::Url;
+
+    use super::{
+        apply_edits, find_edits, find_suggestions, relative_link, replace_link, suggest_fixes,
+        LinkFix, SourceEdit,
+    };
+    use crate::{CheckError, FileError, LinkError, Origin, Redirect, Report, SourceLocation};
+
+    fn fix(old: &str, new: &str) -> LinkFix {
+        LinkFix {
+            source: None,
+            old: old.into(),
+            new: new.into(),
+        }
+    }
+
+    fn fix_at(path: &str, line: usize, old: &str, new: &str) -> LinkFix {
+        LinkFix {
+            source: Some(SourceLocation {
+                path: path.into(),
+                line,
+            }),
+            ..fix(old, new)
+        }
+    }
+
+    #[test]
+    fn test_replace_link() {
+        let redirect = fix("http://example.com", "https://example.org");
+        assert_eq!(
+            replace_link(
+                "/// [a](http://example.com) <http://example.com#x>",
+                &redirect
+            ),
+            "/// [a](https://example.org) <https://example.org#x>"
+        );
+        // Only whole links are replaced
+        let line = "/// [a](http://example.com/page) [b](http://example.com.evil)";
+        assert_eq!(replace_link(line, &redirect), line);
+        assert_eq!(
+            replace_link("/// [a]: http://example.com", &redirect),
+            "/// [a]: https://example.org"
+        );
+
+        let moved = fix("fn.f.html", "a/fn.f.html");
+        assert_eq!(
+            replace_link("//! [f](./fn.f.html) [g](b/fn.f.html)", &moved),
+            "//! [f](./a/fn.f.html) [g](b/fn.f.html)"
+        );
+    }
+
+    #[test]
+    fn test_relative_link() {
+        assert_eq!(
+            relative_link(
+                Path::new("/doc/krate/a/fn.f.html"),
+                Path::new("/doc/krate/b")
+            ),
+            "../a/fn.f.html"
+        );
+        assert_eq!(
+            relative_link(Path::new("/doc/krate/fn.f.html"), Path::new("/doc/krate")),
+            "fn.f.html"
+        );
+    }
+
+    #[test]
+    fn test_suggest_redirect() {
+        let url = Url::parse("http://example.com#top").unwrap();
+        let redirect = |status, location: &str| Redirect {
+            status,
+            location: Url::parse(location).unwrap(),
+        };
+        let mut report = Report::new();
+        report.add_dir(
+            Path::new("/doc/krate"),
+            vec![FileError {
+                path: PathBuf::from("index.html"),
+                errors: vec![CheckError::PermanentRedirect(
+                    url,
+                    vec![
+                        redirect(301, "https://example.com/"),
+                        redirect(302, "https://example.com/login"),
+                    ],
+                )
+                .into()],
+            }],
+        );
+        assert_eq!(
+            suggest_fixes(&report),
+            vec![
+                fix("http://example.com", "https://example.com"),
+                fix("http://example.com/", "https://example.com/"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_suggest_moved_item() {
+        let root = std::env::temp_dir().join(format!("deadlinks-moved-{}", std::process::id()));
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/fn.f.html"), "").unwrap();
+        let missing = |page: &str, link: &str, source: Option<(&str, usize)>| FileError {
+            path: PathBuf::from(page),
+            errors: vec![LinkError {
+                origin: source.map(|(path, line)| Origin {
+                    item: None,
+                    source: Some(SourceLocation {
+                        path: path.into(),
+                        line,
+                    }),
+                }),
+                ..CheckError::File(PathBuf::from(link)).into()
+            }],
+        };
+        let mut report = Report::new();
+        report.add_dir(
+            &root,
+            vec![
+                missing("index.html", "fn.f.html", Some(("src/lib.rs", 1))),
+                missing("b/index.html", "b/fn.f.html", Some(("src/b.rs", 2))),
+                missing("c/index.html", "c/fn.f.html", None),
+            ],
+        );
+        // The same text is fixed differently depending on the module the doc comment is in.
+        assert_eq!(
+            suggest_fixes(&report),
+            vec![
+                fix("fn.f.html", "../a/fn.f.html"),
+                fix_at("src/b.rs", 2, "fn.f.html", "../a/fn.f.html"),
+                fix_at("src/lib.rs", 1, "fn.f.html", "a/fn.f.html"),
+            ]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_fix_sources() {
+        let dir = std::env::temp_dir().join(format!("deadlinks-fix-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        let lib = dir.join("src/lib.rs");
+        fs::write(
+            &lib,
+            "//! See [old](fn.old.html).\r\nconst LINK: &str = \"fn.old.html\";\r\n/// [f](fn.old.html#examples)\r\n",
+        )
+        .unwrap();
+        fs::write(dir.join("target/generated.rs"), "/// [old](fn.old.html)\n").unwrap();
+
+        // Only the line the broken link came from is changed, even though line 1 has the same link.
+        let fixes = [
+            fix_at("src/lib.rs", 3, "fn.old.html", "a/fn.old.html"),
+            fix_at("src/missing.rs", 1, "fn.old.html", "a/fn.old.html"),
+        ];
+        let edits = find_edits(&dir, &fixes).unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].line, 3);
+        assert_eq!(
+            edits[0].to_string(),
+            format!(
+                "--- {0}\n+++ {0}\n@@ -3 +3 @@\n-/// [f](fn.old.html#examples)\n+/// [f](a/fn.old.html#examples)",
+                lib.display()
+            )
+        );
+        assert!(find_suggestions(&dir, &fixes).unwrap().is_empty());
+
+        // Without a source, every doc comment containing the link is a suggestion.
+        let suggestions = find_suggestions(&dir, &[fix("fn.old.html", "a/fn.old.html")]).unwrap();
+        let lines: Vec<_> = suggestions.iter().map(|edit| edit.line).collect();
+        assert_eq!(lines, [1, 3]);
+
+        assert_eq!(apply_edits(&edits).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(&lib).unwrap(),
+            "//! See [old](fn.old.html).\r\nconst LINK: &str = \"fn.old.html\";\r\n/// [f](a/fn.old.html#examples)\r\n"
+        );
+
+        // Edits for lines which changed or no longer exist are skipped.
+        let stale = [
+            edits[0].clone(),
+            SourceEdit {
+                line: 10,
+                ..edits[0].clone()
+            },
+            SourceEdit {
+                line: 0,
+                ..edits[0].clone()
+            },
+        ];
+        assert_eq!(apply_edits(&stale).unwrap(), 0);
+        assert_eq!(
+            fs::read_to_string(&lib).unwrap(),
+            "//! See [old](fn.old.html).\r\nconst LINK: &str = \"fn.old.html\";\r\n/// [f](a/fn.old.html#examples)\r\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! Rules for ignoring links and HTML files.
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use regex::Regex;
+use serde::de::{Deserialize, Deserializer, Error as _};
+
+/// A glob or regular expression matching link targets or file paths.
+///
+/// Patterns starting with `regex:` are regular expressions, which match if they match any part of the input.
+/// All other patterns are globs, which must match the whole input:
+/// `?` matches any character except `/`, `*` matches any number of characters except `/`,
+/// and `**` matches any number of characters including `/`.
+#[derive(Clone, Debug)]
+pub struct IgnorePattern {
+    source: String,
+    regex: Regex,
+}
+
+impl IgnorePattern {
+    /// The pattern as it was originally written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        self.regex.is_match(input)
+    }
+}
+
+impl FromStr for IgnorePattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let regex = match pattern.strip_prefix("regex:") {
+            Some(regex) => Regex::new(regex),
+            None => Regex::new(&glob_to_regex(pattern)),
+        };
+        regex
+            .map(|regex| IgnorePattern {
+                source: pattern.to_owned(),
+                regex,
+            })
+            .map_err(|err| format!("invalid pattern '{}': {}", pattern, err))
+    }
+}
+
+impl PartialEq for IgnorePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl fmt::Display for IgnorePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for IgnorePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        pattern.parse().map_err(D::Error::custom)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` also matches no directories at all
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// A list of patterns, counting how often each one matched.
+///
+/// This is shared between threads, so that the counts include every file that was checked.
+#[derive(Debug, Default)]
+pub struct IgnoreList {
+    rules: Vec<(IgnorePattern, AtomicUsize)>,
+}
+
+impl IgnoreList {
+    pub fn new(patterns: impl IntoIterator<Item = IgnorePattern>) -> Self {
+        IgnoreList {
+            rules: patterns
+                .into_iter()
+                .map(|pattern| (pattern, AtomicUsize::new(0)))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether any pattern matches `input`. The first matching pattern has its count incremented.
+    pub fn matches(&self, input: &str) -> bool {
+        match self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(input))
+        {
+            Some((_, count)) => {
+                count.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every pattern, along with how many times it matched so far.
+    pub fn counts(&self) -> impl Iterator<Item = (&IgnorePattern, usize)> {
+        self.rules
+            .iter()
+            .map(|(pattern, count)| (pattern, count.load(Ordering::Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IgnoreList, IgnorePattern};
+
+    fn pattern(s: &str) -> IgnorePattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_globs() {
+        let all_of_host = pattern("https://example.com/**");
+        assert!(all_of_host.is_match("https://example.com/"));
+        assert!(all_of_host.is_match("https://example.com/a/b?c=d"));
+        assert!(!all_of_host.is_match("https://example.com.evil/"));
+
+        let one_level = pattern("src/*.html");
+        assert!(one_level.is_match("src/lib.rs.html"));
+        assert!(!one_level.is_match("src/foo/lib.rs.html"));
+
+        let any_dir = pattern("**/generated?.html");
+        assert!(any_dir.is_match("generated1.html"));
+        assert!(any_dir.is_match("a/b/generated2.html"));
+        assert!(!any_dir.is_match("a/generated.html"));
+
+        // regex metacharacters are matched literally
+        assert!(pattern("a+b.html").is_match("a+b.html"));
+        assert!(!pattern("a+b.html").is_match("aab.html"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let regex = pattern(r"regex:^https?://(www\.)?linkedin\.com");
+        assert!(regex.is_match("https://www.linkedin.com/in/someone"));
+        assert!(!regex.is_match("https://example.com/?linkedin.com"));
+        assert!(pattern("regex:login").is_match("https://example.com/login?next=/"));
+        assert!("regex:(".parse::<IgnorePattern>().is_err());
+    }
+
+    #[test]
+    fn test_counts() {
+        let list = IgnoreList::new(vec![pattern("*.html"), pattern("regex:html")]);
+        assert!(list.matches("a.html"));
+        assert!(list.matches("dir/a.html"));
+        assert!(!list.matches("a.css"));
+        let counts: Vec<_> = list.counts().map(|(p, n)| (p.as_str(), n)).collect();
+        assert_eq!(counts, vec![("*.html", 1), ("regex:html", 1)]);
+    }
+}
@@ -0,0 +1,389 @@
+//! An index of the HTML pages in a directory: which pages exist, their anchors and redirects, and the links between them.
+//!
+//! Checking happens in two phases. First every page is read once, in parallel, and added to a [`SiteIndex`].
+//! Then each link is resolved against the index, so pages which are linked to many times are never read again.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use log::info;
+use rayon::prelude::*;
+use url::Url;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::parse::{self, LinkBase, PageError, ParsedLink};
+use crate::{is_html_file, is_ignored_file, markdown, CheckContext, CheckError, Link, LinkError};
+
+/// Everything the index knows about a single page.
+#[derive(Debug, Default)]
+pub(crate) struct IndexedPage {
+    /// The `id` attributes of all elements, and the `name` attributes of `<a>` elements.
+    /// For Markdown files, the anchors of their headings.
+    pub(crate) anchors: HashSet<String>,
+    /// The file `<meta http-equiv="refresh">` sends browsers to.
+    pub(crate) redirect: Option<PathBuf>,
+    /// The links on the page; empty for pages outside of the indexed directory.
+    pub(crate) links: Vec<ParsedLink>,
+    pub(crate) broken_intra_doc_links: Vec<LinkError>,
+}
+
+/// A page in the index, or the reason it could not be read.
+pub(crate) type IndexEntry = Arc<Result<IndexedPage, CheckError>>;
+
+/// The HTML pages below a directory, along with their anchors, redirects and links.
+///
+/// The index can be used to query the link graph of the documentation, e.g. which pages link to a page.
+/// Pages outside of the directory (e.g. the standard library's documentation) are read the first time a link
+/// points into them, and kept for later links. [`SiteIndex::default`] is an empty index which only does the latter.
+#[derive(Debug, Default)]
+pub struct SiteIndex {
+    root: PathBuf,
+    pub(crate) pages: HashMap<PathBuf, IndexEntry>,
+    /// Pages below `root` which match [`CheckContext::ignore_files`]. They are only used to follow links through.
+    ignored: HashMap<PathBuf, IndexEntry>,
+    /// The pages linking to each page, sorted by path.
+    linking: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Pages outside of `root`, and other files links point to.
+    others: RwLock<HashMap<PathBuf, IndexEntry>>,
+}
+
+impl SiteIndex {
+    /// Read every HTML file below `dir` in parallel.
+    ///
+    /// Files which match [`CheckContext::ignore_files`] are not among [`SiteIndex::pages`], but are still read,
+    /// so that [`SiteIndex::unreachable_pages`] can follow links through them.
+    ///
+    /// Links are resolved like [`unavailable_urls`](crate::unavailable_urls) does, using [`CheckContext::site_dir`],
+    /// [`CheckContext::site_root`] and [`CheckContext::base_url`]. `dir` must be absolute.
+    pub fn build(dir: &Path, ctx: &CheckContext) -> SiteIndex {
+        let root_url = Url::from_directory_path(ctx.site_dir.as_deref().unwrap_or(dir)).unwrap();
+        let site_root = ctx
+            .site_root
+            .as_deref()
+            .or_else(|| ctx.base_url.as_ref().map(Url::path))
+            .unwrap_or("/");
+
+        let index_page = |entry: DirEntry, intra_doc_links: bool| {
+            let path = entry.path();
+            info!("Indexing doc page at {}", path.display());
+            let file_url = Url::from_file_path(path).unwrap();
+            let base = LinkBase {
+                root_url: &root_url,
+                site_root,
+                base_url: ctx.base_url.as_ref(),
+                file_url: &file_url,
+            };
+            let page = read_page(path, Some(&base), intra_doc_links);
+            (DirEntry::into_path(entry), Arc::new(page))
+        };
+
+        let mut ignored = Vec::new();
+        let pages = WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|entry| {
+                let skip = is_ignored_file(entry, dir, ctx);
+                if skip {
+                    ignored.push(entry.path().to_owned());
+                }
+                !skip
+            })
+            .par_bridge()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file() && is_html_file(entry))
+            .map(|entry| index_page(entry, ctx.check_intra_doc_links))
+            .collect();
+        // Errors in ignored pages are never reported, so there is no need to look for broken intra-doc links.
+        let ignored = ignored
+            .into_iter()
+            .flat_map(WalkDir::new)
+            .par_bridge()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file() && is_html_file(entry))
+            .map(|entry| index_page(entry, false))
+            .collect();
+
+        let mut index = SiteIndex {
+            root: dir.to_owned(),
+            pages,
+            ignored,
+            linking: HashMap::new(),
+            others: RwLock::default(),
+        };
+        index.linking = index.reverse_links();
+        index
+    }
+
+    /// For each page, the pages which link to it.
+    fn reverse_links(&self) -> HashMap<PathBuf, Vec<PathBuf>> {
+        let mut linking: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for page in self.pages() {
+            for target in self.linked_pages(page) {
+                linking
+                    .entry(target.to_owned())
+                    .or_default()
+                    .push(page.to_owned());
+            }
+        }
+        for pages in linking.values_mut() {
+            pages.sort();
+        }
+        linking
+    }
+
+    /// The directory that was indexed.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The paths of all pages below [`SiteIndex::root`], in no particular order.
+    pub fn pages(&self) -> impl Iterator<Item = &Path> {
+        self.pages.keys().map(PathBuf::as_path)
+    }
+
+    /// Whether `path` is one of [`SiteIndex::pages`].
+    pub fn contains(&self, path: &Path) -> bool {
+        self.pages.contains_key(path)
+    }
+
+    /// The anchors on the page at `path`, or `None` if it is not in the index or could not be read.
+    pub fn anchors(&self, path: &Path) -> Option<&HashSet<String>> {
+        Some(&self.indexed(path)?.anchors)
+    }
+
+    /// The file the page at `path` redirects to, if any.
+    pub fn redirect(&self, path: &Path) -> Option<&Path> {
+        self.indexed(path)?.redirect.as_deref()
+    }
+
+    /// The URLs of all links on the page at `path`, whether or not they are broken.
+    ///
+    /// Links to local files use the `file` scheme. A URL is returned once for each kind of link it was found on.
+    pub fn links(&self, path: &Path) -> impl Iterator<Item = &Url> {
+        self.indexed(path)
+            .into_iter()
+            .flat_map(|page| page.links.iter().map(|link| &link.url))
+    }
+
+    /// The pages in the index the page at `path` links to, in the order they are first linked to.
+    ///
+    /// Links to a directory count as links to its `index.html`.
+    pub fn linked_pages(&self, path: &Path) -> Vec<&Path> {
+        let mut seen = HashSet::new();
+        self.links(path)
+            .filter(|url| url.scheme() == "file")
+            .filter_map(|url| self.page_for(&url.to_file_path().ok()?))
+            .filter(|page| seen.insert(*page))
+            .collect()
+    }
+
+    /// The pages in the index which link to the page at `path`, sorted by path.
+    ///
+    /// These are found while building the index, so this doesn't have to look at every page.
+    pub fn linking_pages(&self, path: &Path) -> Vec<&Path> {
+        self.linking.get(path).map_or_else(Vec::new, |pages| {
+            pages.iter().map(PathBuf::as_path).collect()
+        })
+    }
+
+    /// The pages which can't be reached by following links from `entry_points`, sorted by path.
+    ///
+    /// Links are followed from each page to the pages in [`SiteIndex::linked_pages`], and to the page it redirects to.
+    /// Pages which redirect elsewhere are never returned, since they only exist to keep old links working.
+    /// Links are followed through pages matching [`CheckContext::ignore_files`] as well, but they are never returned.
+    pub fn unreachable_pages(&self, entry_points: &[&Path]) -> Vec<&Path> {
+        let mut reached: HashSet<&Path> = HashSet::new();
+        let mut queue: Vec<&Path> = entry_points
+            .iter()
+            .filter_map(|entry| self.page_for(entry))
+            .collect();
+        while let Some(page) = queue.pop() {
+            if !reached.insert(page) {
+                continue;
+            }
+            queue.extend(self.linked_pages(page));
+            queue.extend(self.redirect(page).and_then(|target| self.page_for(target)));
+        }
+        let mut pages: Vec<_> = self
+            .pages()
+            .filter(|page| !reached.contains(page) && self.redirect(page).is_none())
+            .collect();
+        pages.sort();
+        pages
+    }
+
+    /// The page in the index at `path`, or its `index.html` if it is a directory.
+    ///
+    /// This includes ignored pages, so that links can be followed through them.
+    fn page_for(&self, path: &Path) -> Option<&Path> {
+        let index_html = path.join("index.html");
+        let (page, _) = [path, &index_html].iter().find_map(|path| {
+            self.pages
+                .get_key_value(*path)
+                .or_else(|| self.ignored.get_key_value(*path))
+        })?;
+        Some(page)
+    }
+
+    fn indexed(&self, path: &Path) -> Option<&IndexedPage> {
+        let page = self.pages.get(path).or_else(|| self.ignored.get(path))?;
+        page.as_ref().as_ref().ok()
+    }
+
+    /// Whether `path` is a file, without touching the file system if it is in the index.
+    pub(crate) fn is_file(&self, path: &Path) -> bool {
+        self.pages.contains_key(path) || path.is_file()
+    }
+
+    /// The page at `path`, reading it if it is not in the index yet.
+    pub(crate) fn load(&self, path: &Path) -> IndexEntry {
+        if let Some(page) = self.pages.get(path).or_else(|| self.ignored.get(path)) {
+            return page.clone();
+        }
+        if let Some(page) = self.others.read().unwrap().get(path) {
+            return page.clone();
+        }
+        info!("Reading {} to check its anchors", path.display());
+        let page = if markdown::is_markdown_path(path) {
+            fs::read_to_string(path)
+                .map(|markdown| IndexedPage {
+                    anchors: markdown::parse_anchors(&markdown),
+                    ..IndexedPage::default()
+                })
+                .map_err(|err| CheckError::UnreadableFile(path.to_owned(), Arc::new(err)))
+        } else {
+            read_page(path, None, false)
+        };
+        self.others
+            .write()
+            .unwrap()
+            .entry(path.to_owned())
+            .or_insert_with(|| Arc::new(page))
+            .clone()
+    }
+}
+
+fn read_page(
+    path: &Path,
+    links: Option<&LinkBase>,
+    intra_doc_links: bool,
+) -> Result<IndexedPage, CheckError> {
+    let page = File::open(path)
+        .map_err(PageError::from)
+        .and_then(|file| parse::extract_page(file, links, intra_doc_links))
+        .map_err(|err| match err {
+            PageError::Read(err) => CheckError::UnreadableFile(path.to_owned(), Arc::new(err)),
+            PageError::Parse(err) => CheckError::parse(Link::File(path.to_owned()), &err),
+        })?;
+    Ok(IndexedPage {
+        anchors: page.anchors,
+        // Resolve the redirect like a link, so that `..` is removed and it can be looked up in the index.
+        redirect: page.redirect.and_then(|redirect| {
+            let url = Url::from_file_path(path).ok()?.join(&redirect).ok()?;
+            url.to_file_path().ok()
+        }),
+        links: page.links,
+        broken_intra_doc_links: page.broken_intra_doc_links,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::SiteIndex;
+    use crate::{CheckContext, IgnoreList};
+
+    #[test]
+    fn test_build_index() {
+        let root = env::current_dir().unwrap().join("tests/html");
+        let index = SiteIndex::build(&root, &CheckContext::default());
+        let mut pages: Vec<_> = index
+            .pages()
+            .map(|page| page.strip_prefix(&root).unwrap())
+            .collect();
+        pages.sort();
+        assert_eq!(
+            pages,
+            [
+                Path::new("anchors.html"),
+                Path::new("index.html"),
+                Path::new("range.html")
+            ]
+        );
+
+        let anchors = root.join("anchors.html");
+        let index_html = root.join("index.html");
+        assert!(index.anchors(&anchors).unwrap().contains("h1"));
+        assert!(index.anchors(&root.join("missing.html")).is_none());
+        assert_eq!(index.linked_pages(&index_html), [anchors.as_path()]);
+        // Links to the page itself count as well.
+        assert_eq!(
+            index.linking_pages(&anchors),
+            [anchors.as_path(), index_html.as_path()]
+        );
+        assert!(index.linking_pages(&index_html).is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_pages() {
+        let root = env::current_dir().unwrap().join("tests/orphan_pages");
+        let index = SiteIndex::build(&root, &CheckContext::default());
+        let unreachable = |entry_points: &[&str]| -> Vec<_> {
+            let entry_points: Vec<_> = entry_points.iter().map(|page| root.join(page)).collect();
+            let entry_points: Vec<_> = entry_points.iter().map(|path| path.as_path()).collect();
+            index
+                .unreachable_pages(&entry_points)
+                .into_iter()
+                .map(|page| {
+                    page.strip_prefix(&root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+        assert_eq!(
+            unreachable(&["index.html"]),
+            ["404.html", "orphan.html", "stale.html"]
+        );
+        // Links are followed through redirects, and directories are entry points for their `index.html`.
+        assert!(unreachable(&["", "404.html", "redirect.html"]).is_empty());
+        assert_eq!(index.unreachable_pages(&[]).len(), 7);
+    }
+
+    #[test]
+    fn test_links_through_ignored_pages() {
+        let root = env::current_dir().unwrap().join("tests/orphan_pages");
+        let ctx = CheckContext {
+            ignore_files: Arc::new(IgnoreList::new(vec!["guide/index.html".parse().unwrap()])),
+            ..CheckContext::default()
+        };
+        let index = SiteIndex::build(&root, &ctx);
+        let guide = root.join("guide/index.html");
+        let chapter = root.join("guide/chapter.html");
+        assert!(!index.contains(&guide));
+        assert!(index.contains(&chapter));
+        assert_eq!(
+            index.linked_pages(&guide),
+            [root.join("index.html").as_path(), &chapter]
+        );
+
+        let unreachable = index.unreachable_pages(&[&root.join("index.html")]);
+        assert!(!unreachable.contains(&guide.as_path()));
+        assert!(!unreachable.contains(&chapter.as_path()));
+    }
+
+    #[test]
+    fn test_load_pages_outside_of_the_index() {
+        let index = SiteIndex::default();
+        let path = env::current_dir().unwrap().join("tests/html/range.html");
+        assert!(!index.contains(&path));
+        let page = index.load(&path);
+        assert!(page.as_ref().as_ref().unwrap().anchors.contains("3"));
+        assert!(index.load(&path.with_file_name("missing.html")).is_err());
+    }
+}
@@ -1,24 +1,54 @@
 #![allow(clippy::result_large_err)]
 
 use std::{
-    fmt,
+    collections::{HashMap, HashSet},
+    fmt, iter,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use log::info;
+use log::{debug, info, warn};
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
+use serde_derive::{Deserialize, Serialize};
 use url::Url;
-use walkdir::{DirEntry, WalkDir};
+use walkdir::DirEntry;
 
-use check::is_available;
+use check::is_available_offline;
+use parse::ParsedLink;
 
-pub use check::{CheckError, IoError};
+pub use baseline::{Baseline, BaselineEntry};
+pub use book::Book;
+pub use cache::HttpCache;
+pub use check::{CheckError, IoError, Link};
+pub use config::{parse_duration, Config, ConfigError, CONFIG_FILE_NAME};
+pub use fix::{apply_edits, find_edits, find_suggestions, suggest_fixes, LinkFix, SourceEdit};
+pub use http::{HttpClientConfig, HttpHeader, HttpLimits, Redirect};
+pub use ignore::{IgnoreList, IgnorePattern};
+pub use index::SiteIndex;
+pub use markdown::check_markdown;
+pub use origin::{Origin, SourceLocation};
+pub use parse::{LinkKind, Position};
+pub use report::{Report, REPORT_VERSION};
+pub use rustdoc_json::check_rustdoc_json;
 
+mod baseline;
+mod book;
+mod cache;
 mod check;
+mod config;
+mod fix;
+mod http;
+mod ignore;
+mod index;
+mod markdown;
+mod origin;
 mod parse;
+mod report;
+mod rustdoc_json;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 /// What behavior should deadlinks use for HTTP links?
 pub enum HttpCheck {
     /// Make an internet request to ensure the link works
@@ -39,6 +69,64 @@ pub struct CheckContext {
     pub check_http: HttpCheck,
     pub check_fragments: bool,
     pub check_intra_doc_links: bool,
+    /// Which kinds of links to check. Links found on other elements are ignored.
+    pub link_kinds: HashSet<LinkKind>,
+    /// A persistent cache of HTTP results, shared between runs.
+    pub http_cache: Option<Arc<HttpCache>>,
+    /// How many HTTP requests may be sent at once, and how quickly.
+    pub http_limits: HttpLimits,
+    /// Timeouts, headers, and other settings for the HTTP client.
+    pub http_client: HttpClientConfig,
+    /// Links whose target matches one of these patterns are not checked.
+    ///
+    /// Links to local files are matched by their path relative to the directory being checked;
+    /// all other links are matched by their full URL. Fragments are not included in either case.
+    pub ignore_links: Arc<IgnoreList>,
+    /// Report errors which might go away by themselves (see [`CheckError::is_transient`]) as errors
+    /// instead of warnings.
+    pub deny_transient_errors: bool,
+    /// Report links which redirect to a different host as errors, e.g. because they redirect to a login page.
+    pub deny_cross_host_redirects: bool,
+    /// HTML and Markdown files and directories matching one of these patterns are not checked.
+    ///
+    /// Paths are relative to the directory being checked and always use `/` as a separator.
+    pub ignore_files: Arc<IgnoreList>,
+    /// The directory containing the root module of each crate (e.g. `src/` for `src/lib.rs`), by crate name.
+    ///
+    /// Used to show the Rust source file a broken link was written in. Crates which are not listed are shown
+    /// relative to rustdoc's `src/` directory.
+    pub source_roots: HashMap<String, PathBuf>,
+    /// The path [`CheckContext::site_dir`] is served under, e.g. `/my-book/`.
+    ///
+    /// Links to absolute paths starting with it are resolved relative to `site_dir`, and other absolute paths
+    /// are not checked. When unset, this is the path of [`CheckContext::base_url`], or `/`.
+    pub site_root: Option<String>,
+    /// The URL [`CheckContext::site_dir`] is deployed at, e.g. `https://example.com/docs/`.
+    ///
+    /// Links to full URLs on the same site are checked like absolute paths, against the local files instead of over
+    /// HTTP, as long as they start with [`CheckContext::site_root`].
+    pub base_url: Option<Url>,
+    /// The local directory absolute paths are resolved against. When unset, this is the directory being checked.
+    ///
+    /// `cargo deadlinks` sets this to `target/doc`, which contains the documentation of every crate.
+    pub site_dir: Option<PathBuf>,
+    /// The documentation of the `rust-docs` component, i.e. the directory containing the file printed by `rustup doc --path`.
+    ///
+    /// When set, links to `https://doc.rust-lang.org` are checked against it instead of being assumed to be valid,
+    /// as long as the crate or book they point into is part of it.
+    pub std_docs: Option<PathBuf>,
+    /// A directory containing the documentation of dependencies, usually `target/doc`.
+    ///
+    /// When set, links to `https://docs.rs` are checked against it instead of over HTTP, as long as the crate they
+    /// point into is documented in it. The version in the link is ignored.
+    pub dependency_docs: Option<PathBuf>,
+    /// Report pages which can't be reached by following links from `index.html` or [`CheckContext::entry_points`],
+    /// e.g. items hidden from navigation or stale files left over from previous builds.
+    pub check_orphan_pages: bool,
+    /// Pages links are followed from when looking for orphaned pages, in addition to `index.html`.
+    ///
+    /// Paths are relative to the directory being checked and always use `/` as a separator.
+    pub entry_points: Vec<String>,
 }
 
 impl Default for CheckContext {
@@ -48,50 +136,168 @@ impl Default for CheckContext {
             verbose: false,
             check_fragments: true,
             check_intra_doc_links: false,
+            link_kinds: LinkKind::ALL.iter().copied().collect(),
+            http_cache: None,
+            http_limits: HttpLimits::default(),
+            http_client: HttpClientConfig::default(),
+            deny_transient_errors: false,
+            deny_cross_host_redirects: false,
+            ignore_links: Arc::default(),
+            ignore_files: Arc::default(),
+            source_roots: HashMap::new(),
+            site_root: None,
+            base_url: None,
+            site_dir: None,
+            std_docs: None,
+            dependency_docs: None,
+            check_orphan_pages: false,
+            entry_points: Vec::new(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct FileError {
     pub path: PathBuf,
-    pub errors: Vec<CheckError>,
+    pub errors: Vec<LinkError>,
+}
+
+/// How serious an error is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The link is broken.
+    Error,
+    /// The link might be broken, but the error might also go away by itself.
+    /// Warnings are reported, but don't cause the check to fail.
+    Warning,
+}
+
+/// An error, along with where in the file it was found.
+#[derive(Clone, Debug, Serialize)]
+pub struct LinkError {
+    /// The position of the element containing the broken link, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+    pub severity: Severity,
+    /// The item and Rust source the link was written in, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    #[serde(flatten)]
+    pub error: CheckError,
+}
+
+impl LinkError {
+    fn new(position: Option<Position>, error: CheckError, ctx: &CheckContext) -> Self {
+        let warning = match error {
+            // The link still works, but should be updated.
+            CheckError::PermanentRedirect(..) => true,
+            _ => error.is_transient() && !ctx.deny_transient_errors,
+        };
+        let severity = if warning {
+            Severity::Warning
+        } else {
+            Severity::Error
+        };
+        LinkError {
+            position,
+            severity,
+            origin: None,
+            error,
+        }
+    }
+}
+
+impl From<CheckError> for LinkError {
+    fn from(error: CheckError) -> Self {
+        LinkError {
+            position: None,
+            severity: Severity::Error,
+            origin: None,
+            error,
+        }
+    }
 }
 
 impl fmt::Display for FileError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Found invalid urls in {}:", self.path.display())?;
         for e in &self.errors {
-            write!(f, "\n\t{}", e)?;
+            let severity = match e.severity {
+                Severity::Error => "",
+                Severity::Warning => "warning: ",
+            };
+            match e.position {
+                Some(pos) => write!(
+                    f,
+                    "\n\t{}:{}: {}{}",
+                    self.path.display(),
+                    pos,
+                    severity,
+                    e.error
+                )?,
+                None => write!(f, "\n\t{}{}", severity, e.error)?,
+            }
+            if let Some(origin) = &e.origin {
+                write!(f, "\n\t  --> {}", origin)?;
+            }
         }
         Ok(())
     }
 }
 
+impl FileError {
+    /// Whether any of the errors in this file are not just warnings.
+    pub fn has_errors(&self) -> bool {
+        self.errors.iter().any(|e| e.severity == Severity::Error)
+    }
+}
+
 /// Traverses a given path recursively, checking all *.html files found.
 ///
 /// For each error that occurred, print an error message.
-/// Returns whether an error occurred.
+/// Returns whether an error occurred, not counting warnings.
 pub fn walk_dir(dir_path: &Path, ctx: &CheckContext) -> bool {
-    let pool = ThreadPoolBuilder::new()
-        .num_threads(num_cpus::get())
-        .build()
-        .unwrap();
-
-    pool.install(|| {
+    thread_pool().install(|| {
         unavailable_urls(dir_path, ctx)
             .map(|mut err| {
                 if !ctx.verbose {
                     err.shorten_all(dir_path);
                 }
                 println!("{}", err);
-                true
+                err.has_errors()
             })
             // ||||||
             .reduce(|| false, |initial, new| initial || new)
     })
 }
 
+/// Traverses a given path recursively, checking all *.html files found.
+///
+/// Returns all errors that occurred, sorted by path.
+/// Unless `ctx.verbose` is set, paths are relative to `dir_path`.
+pub fn check_dir(dir_path: &Path, ctx: &CheckContext) -> Vec<FileError> {
+    let mut errors: Vec<_> = thread_pool().install(|| {
+        unavailable_urls(dir_path, ctx)
+            .map(|mut err| {
+                if !ctx.verbose {
+                    err.shorten_all(dir_path);
+                }
+                err
+            })
+            .collect()
+    });
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    errors
+}
+
+fn thread_pool() -> rayon::ThreadPool {
+    ThreadPoolBuilder::new()
+        .num_threads(num_cpus::get())
+        .build()
+        .unwrap()
+}
+
 impl FileError {
     fn shorten_all(&mut self, prefix: &Path) {
         use check::Link;
@@ -99,8 +305,12 @@ impl FileError {
         if let Ok(shortened) = self.path.strip_prefix(prefix) {
             self.path = shortened.to_path_buf();
         };
-        for mut e in &mut self.errors {
-            if let CheckError::File(epath) | CheckError::Fragment(Link::File(epath), _, _) = &mut e
+        for e in &mut self.errors {
+            if let CheckError::File(epath)
+            | CheckError::Fragment(Link::File(epath), _, _)
+            | CheckError::Parse(Link::File(epath), _)
+            | CheckError::UnreadableFile(epath, _)
+            | CheckError::OrphanPage(epath) = &mut e.error
             {
                 if let Ok(shortened) = epath.strip_prefix(prefix) {
                     *epath = shortened.to_path_buf();
@@ -110,6 +320,67 @@ impl FileError {
     }
 }
 
+/// The path of `path` relative to `root`, with `/` as a separator, or `None` if it is not inside `root`.
+fn relative_path(path: &Path, root: &Path) -> Option<String> {
+    path.strip_prefix(root).ok().map(portable_path)
+}
+
+/// A relative path with `/` as a separator, whatever the platform.
+pub(crate) fn portable_path(path: &Path) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+fn is_ignored_file(entry: &DirEntry, root: &Path, ctx: &CheckContext) -> bool {
+    if entry.depth() == 0 || ctx.ignore_files.is_empty() {
+        return false;
+    }
+    // Other files are never checked, so don't count them as ignored.
+    if !entry.file_type().is_dir() && !is_html_file(entry) && !is_markdown_file(entry) {
+        return false;
+    }
+    let path = match relative_path(entry.path(), root) {
+        Some(path) => path,
+        None => return false,
+    };
+    // Allow `dir/**` to skip the whole directory instead of each file inside it.
+    let ignored = ctx.ignore_files.matches(&path)
+        || (entry.file_type().is_dir() && ctx.ignore_files.matches(&format!("{}/", path)));
+    if ignored {
+        info!(
+            "Skipping {} as it matches an ignore rule",
+            entry.path().display()
+        );
+    }
+    ignored
+}
+
+fn is_ignored_link(url: &Url, root: &Path, ctx: &CheckContext) -> bool {
+    if ctx.ignore_links.is_empty() {
+        return false;
+    }
+    let target = if url.scheme() == "file" {
+        url.to_file_path()
+            .ok()
+            .and_then(|path| relative_path(&path, root))
+    } else {
+        None
+    };
+    let target = target.unwrap_or_else(|| {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        url.into()
+    });
+    let ignored = ctx.ignore_links.matches(&target);
+    if ignored {
+        debug!("Skip checking {} as it matches an ignore rule", url);
+    }
+    ignored
+}
+
 fn is_html_file(entry: &DirEntry) -> bool {
     match entry.path().extension() {
         Some(e) => e.to_str().map(|ext| ext == "html").unwrap_or(false),
@@ -117,40 +388,147 @@ fn is_html_file(entry: &DirEntry) -> bool {
     }
 }
 
+fn is_markdown_file(entry: &DirEntry) -> bool {
+    markdown::is_markdown_path(entry.path())
+}
+
+/// Traverses a given path recursively, checking all *.html files found.
+///
+/// All pages are read into a [`SiteIndex`] first, and links to local files are checked against it.
+/// HTTP links are collected and checked afterwards, so that each URL is only requested once no matter
+/// how many files link to it.
 pub fn unavailable_urls<'a>(
     dir_path: &'a Path,
     ctx: &'a CheckContext,
 ) -> impl ParallelIterator<Item = FileError> + 'a {
-    let root_url = Url::from_directory_path(dir_path).unwrap();
-
-    WalkDir::new(dir_path)
-        .into_iter()
-        .par_bridge()
-        .filter_map(Result::ok)
-        .filter(|entry| entry.file_type().is_file() && is_html_file(entry))
-        .flat_map(move |entry| {
-            let path = entry.path();
-            info!("Checking doc page at {}", path.display());
-            let html = std::fs::read_to_string(path)
-                .unwrap_or_else(|e| panic!("{} did not contain valid UTF8: {}", path.display(), e));
-
-            let file_url = Url::from_file_path(path).unwrap();
-            let urls = parse::parse_a_hrefs(&html, &root_url, &file_url);
-            let broken_intra_doc_links = if ctx.check_intra_doc_links {
-                parse::broken_intra_doc_links(&html)
-            } else {
-                Vec::new()
+    let index = SiteIndex::build(dir_path, ctx);
+    let orphans: HashSet<&Path> = if ctx.check_orphan_pages {
+        orphan_pages(&index, ctx).into_iter().collect()
+    } else {
+        HashSet::new()
+    };
+    let files: Vec<_> = index
+        .pages
+        .par_iter()
+        .filter_map(|(path, page)| {
+            let page = match page.as_ref() {
+                Ok(page) => page,
+                Err(error) => return Some(broken_file(path, error.clone())),
             };
-            let errors = urls
-                .into_iter()
-                .filter_map(|url| is_available(&url, ctx).err())
-                .chain(broken_intra_doc_links)
-                .collect::<Vec<_>>();
+            let mut errors = page.broken_intra_doc_links.clone();
+            if orphans.contains(path.as_path()) {
+                errors.push(LinkError::new(
+                    None,
+                    CheckError::OrphanPage(path.clone()),
+                    ctx,
+                ));
+            }
+            let pending = check_links_offline(&page.links, dir_path, ctx, &index, &mut errors);
+
+            if errors.is_empty() && pending.is_empty() {
+                None
+            } else {
+                Some((path.clone(), errors, pending))
+            }
+        })
+        .collect();
+
+    merge_http_results(files, ctx).map(move |mut file| {
+        origin::attribute_errors(&file.path, &mut file.errors, ctx);
+        file
+    })
+}
+
+/// The pages which can't be reached from `index.html` or [`CheckContext::entry_points`].
+///
+/// Entry points which don't exist are skipped with a warning. If none of them exist, no pages are reported,
+/// since every page would be.
+fn orphan_pages<'a>(index: &'a SiteIndex, ctx: &CheckContext) -> Vec<&'a Path> {
+    let entry_points: Vec<_> = iter::once("index.html")
+        .chain(ctx.entry_points.iter().map(String::as_str))
+        .map(|entry| {
+            entry
+                .trim_start_matches('/')
+                .split('/')
+                .fold(index.root().to_owned(), |path, part| path.join(part))
+        })
+        .filter(|path| {
+            let exists = index.contains(path) || index.contains(&path.join("index.html"));
+            if !exists {
+                warn!("Entry point {} does not exist", path.display());
+            }
+            exists
+        })
+        .collect();
+    if entry_points.is_empty() {
+        return Vec::new();
+    }
+    let entry_points: Vec<_> = entry_points.iter().map(PathBuf::as_path).collect();
+    index.unreachable_pages(&entry_points)
+}
+
+/// Check `links` without sending any HTTP requests, adding the broken ones to `errors`.
+///
+/// Returns the HTTP links which have to be fetched, along with where they were found.
+fn check_links_offline(
+    links: &[ParsedLink],
+    root: &Path,
+    ctx: &CheckContext,
+    index: &SiteIndex,
+    errors: &mut Vec<LinkError>,
+) -> Vec<(Url, Position)> {
+    let mut pending = Vec::new();
+    // A URL can be linked to by different kinds of elements; only check it for the first one that was selected.
+    let mut seen = HashSet::new();
+    for link in links
+        .iter()
+        .filter(|link| ctx.link_kinds.contains(&link.kind))
+        .filter(|link| seen.insert(&link.url))
+        .filter(|link| !is_ignored_link(&link.url, root, ctx))
+    {
+        match is_available_offline(&link.url, ctx, index) {
+            Some(Ok(())) => {}
+            Some(Err(error)) => errors.push(LinkError::new(Some(link.position), error, ctx)),
+            None => pending.push((link.url.clone(), link.position)),
+        }
+    }
+    pending
+}
+
+/// A file which could not be read or parsed, with the reason as its only error.
+fn broken_file(path: &Path, error: CheckError) -> PendingFile {
+    (path.to_owned(), vec![error.into()], Vec::new())
+}
+
+/// A file with the errors found without sending any HTTP requests, and the HTTP links which still have to be fetched.
+type PendingFile = (PathBuf, Vec<LinkError>, Vec<(Url, Position)>);
+
+/// Fetch the pending HTTP links of all files at once, and add the broken ones to the errors of each file.
+///
+/// Files without any errors are left out.
+fn merge_http_results(
+    files: Vec<PendingFile>,
+    ctx: &CheckContext,
+) -> impl ParallelIterator<Item = FileError> + '_ {
+    let urls = files
+        .iter()
+        .flat_map(|(_, _, pending)| pending.iter().map(|(url, _)| url.clone()));
+    let http_results = http::check_urls(urls, ctx);
+
+    files
+        .into_par_iter()
+        .filter_map(move |(path, mut errors, pending)| {
+            errors.extend(pending.into_iter().filter_map(|(url, position)| {
+                http_results[&url]
+                    .clone()
+                    .err()
+                    .map(|error| LinkError::new(Some(position), error, ctx))
+            }));
+            errors.sort_by_key(|e| e.position.map(|pos| pos.offset));
 
             if errors.is_empty() {
                 None
             } else {
-                let path = entry.path().to_owned();
                 Some(FileError { path, errors })
             }
         })
@@ -0,0 +1,321 @@
+//! Checking links in Markdown files, such as `README.md`, `CHANGELOG.md` or the chapters of a book.
+//!
+//! Links are resolved the way GitHub renders them: relative to the file they are written in,
+//! with `/` meaning the directory being checked. Fragments point at headings, using the same
+//! slugs as GitHub (see [`heading_slug`]), or at `id` and `name` attributes in inline HTML.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::{debug, info};
+use lol_html::{element, RewriteStrSettings};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use rayon::prelude::*;
+use url::Url;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::parse::{self, LineIndex, ParsedLink};
+use crate::{
+    broken_file, check_links_offline, is_ignored_file, merge_http_results, thread_pool,
+    CheckContext, CheckError, FileError, LinkKind, SiteIndex,
+};
+
+/// Directories which never contain Markdown worth checking, e.g. build output.
+const SKIPPED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// Whether `path` is a Markdown file, judging by its extension.
+pub(crate) fn is_markdown_path(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext == "md" || ext == "markdown",
+        None => false,
+    }
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_STRIKETHROUGH
+}
+
+/// Whether to skip a directory while looking for Markdown files: hidden directories
+/// such as `.git`, and directories in [`SKIPPED_DIRS`].
+fn is_skipped_dir(entry: &DirEntry) -> bool {
+    if entry.depth() == 0 || !entry.file_type().is_dir() {
+        return false;
+    }
+    let name = entry.file_name().to_string_lossy();
+    name.starts_with('.') || SKIPPED_DIRS.contains(&name.as_ref())
+}
+
+/// Checks the links in a Markdown file, or in all Markdown files (`*.md`) below a directory.
+///
+/// Hidden directories and build output (`target` and `node_modules`) are skipped, as are files
+/// matching [`CheckContext::ignore_files`]. Links to directories are accepted without an `index.html`,
+/// since GitHub lists their contents instead.
+///
+/// `path` must be absolute. Returns all errors that occurred, sorted by path.
+/// Unless `ctx.verbose` is set, paths are relative to `path`, or to its parent if it is a file.
+pub fn check_markdown(path: &Path, ctx: &CheckContext) -> Vec<FileError> {
+    let root = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+    let root_url = Url::from_directory_path(root).unwrap();
+    // Markdown files are only read once their anchors are needed.
+    let index = SiteIndex::default();
+
+    let paths: Vec<_> = WalkDir::new(path)
+        .into_iter()
+        .filter_entry(|entry| !is_skipped_dir(entry) && !is_ignored_file(entry, root, ctx))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file() && is_markdown_path(entry.path()))
+        .map(DirEntry::into_path)
+        .collect();
+
+    let mut errors: Vec<_> = thread_pool().install(|| {
+        let files = paths
+            .into_par_iter()
+            .filter_map(|path| {
+                info!("Checking Markdown file at {}", path.display());
+                let markdown = match std::fs::read_to_string(&path) {
+                    Ok(markdown) => markdown,
+                    Err(err) => {
+                        let error = CheckError::UnreadableFile(path.clone(), Arc::new(err));
+                        return Some(broken_file(&path, error));
+                    }
+                };
+
+                let file_url = Url::from_file_path(&path).unwrap();
+                let links: Vec<_> = parse_links(&markdown, &root_url, &file_url)
+                    .into_iter()
+                    .filter(|link| !is_directory_link(&link.url))
+                    .collect();
+                let mut errors = Vec::new();
+                let pending = check_links_offline(&links, root, ctx, &index, &mut errors);
+
+                if errors.is_empty() && pending.is_empty() {
+                    None
+                } else {
+                    Some((path, errors, pending))
+                }
+            })
+            .collect();
+        merge_http_results(files, ctx)
+            .map(|mut err| {
+                if !ctx.verbose {
+                    err.shorten_all(root);
+                }
+                err
+            })
+            .collect()
+    });
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    errors
+}
+
+fn is_directory_link(url: &Url) -> bool {
+    url.scheme() == "file"
+        && url.fragment().is_none()
+        && matches!(url.to_file_path(), Ok(path) if path.is_dir())
+}
+
+/// Return all links and images in the Markdown file, including the ones in inline HTML.
+///
+/// Each URL is only returned once for each [`LinkKind`], for the first link of that kind it was found in.
+/// Inline links, reference links and autolinks are all returned; references without
+/// a definition are rendered as plain text, so they are not links.
+pub(crate) fn parse_links(markdown: &str, root_url: &Url, file_url: &Url) -> Vec<ParsedLink> {
+    let lines = LineIndex::new(markdown);
+    let mut seen = HashSet::new();
+    let mut links = Vec::new();
+    let mut add_link = |link: ParsedLink| {
+        if seen.insert((link.url.clone(), link.kind)) {
+            links.push(link);
+        }
+    };
+
+    for (event, range) in Parser::new_ext(markdown, options()).into_offset_iter() {
+        let (dest, element, attribute, kind) = match event {
+            Event::Start(Tag::Link(_, dest, _)) => (dest, "a", "href", LinkKind::Hyperlink),
+            Event::Start(Tag::Image(_, dest, _)) => (dest, "img", "src", LinkKind::Media),
+            Event::Html(html) => {
+                match parse::parse_links(&html, root_url, "/", None, file_url) {
+                    Ok(links) => {
+                        for mut link in links {
+                            link.position = lines.position(range.start + link.position.offset);
+                            add_link(link);
+                        }
+                    }
+                    Err(err) => debug!("could not parse inline HTML: {}", err),
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        // Like in HTML, absolute paths are relative to the directory being checked.
+        let url = match dest.strip_prefix('/') {
+            Some(absolute) => root_url.join(absolute),
+            None => file_url.join(&dest),
+        };
+        match url {
+            Ok(url) => add_link(ParsedLink {
+                url,
+                element,
+                attribute,
+                kind,
+                position: lines.position(range.start),
+            }),
+            Err(_) => debug!("unparsable link {:?}", dest),
+        }
+    }
+    links
+}
+
+/// Return the anchor of every heading in the Markdown file, along with all `id` and `name`
+/// attributes in inline HTML.
+///
+/// Headings with the same slug get a numbered suffix, e.g. `usage`, `usage-1` and `usage-2`.
+pub(crate) fn parse_anchors(markdown: &str) -> HashSet<String> {
+    let mut anchors = HashSet::new();
+    let mut heading = None;
+    let mut html = String::new();
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::Heading(..)) => heading = Some(String::new()),
+            Event::End(Tag::Heading(..)) => {
+                let slug = heading_slug(&heading.take().unwrap_or_default());
+                let mut anchor = slug.clone();
+                let mut n = 0;
+                while anchors.contains(&anchor) {
+                    n += 1;
+                    anchor = format!("{}-{}", slug, n);
+                }
+                anchors.insert(anchor);
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(heading) = &mut heading {
+                    heading.push_str(&text);
+                }
+            }
+            Event::Html(fragment) => html.push_str(&fragment),
+            _ => {}
+        }
+    }
+    anchors.extend(html_anchors(&html));
+    anchors
+}
+
+/// The anchor GitHub generates for a heading: lowercase, without punctuation, and with a `-` for each space.
+fn heading_slug(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The `id` attributes of all elements, and the `name` attributes of `<a>` elements.
+fn html_anchors(html: &str) -> HashSet<String> {
+    let anchors = RefCell::new(HashSet::new());
+    let result = lol_html::rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("*[id]", |el| {
+                    anchors.borrow_mut().insert(el.get_attribute("id").unwrap());
+                    Ok(())
+                }),
+                element!("a[name]", |el| {
+                    anchors
+                        .borrow_mut()
+                        .insert(el.get_attribute("name").unwrap());
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    );
+    if let Err(err) = result {
+        debug!("could not parse inline HTML: {}", err);
+    }
+    anchors.into_inner()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use url::Url;
+
+    use super::{heading_slug, parse_anchors, parse_links};
+    use crate::LinkKind;
+
+    #[test]
+    fn test_parse_links() {
+        let markdown = "# Title\n\
+            See [the guide](docs/guide.md#usage), <https://example.com> and [the changelog][log].\n\
+            \n\
+            ![logo](/logo.png) <img src=\"badge.svg\"> [missing reference] `[not](a-link)`\n\
+            \n\
+            ```\n[not](a-link)\n```\n\
+            \n\
+            [log]: CHANGELOG.md\n";
+        let root = Url::parse("file:///repo/").unwrap();
+        let file = Url::parse("file:///repo/sub/README.md").unwrap();
+        let links: Vec<_> = parse_links(markdown, &root, &file)
+            .into_iter()
+            .map(|link| (link.url.to_string(), link.kind, link.position.line))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (
+                    "file:///repo/sub/docs/guide.md#usage".to_owned(),
+                    LinkKind::Hyperlink,
+                    2
+                ),
+                ("https://example.com/".to_owned(), LinkKind::Hyperlink, 2),
+                (
+                    "file:///repo/sub/CHANGELOG.md".to_owned(),
+                    LinkKind::Hyperlink,
+                    2
+                ),
+                ("file:///repo/logo.png".to_owned(), LinkKind::Media, 4),
+                ("file:///repo/sub/badge.svg".to_owned(), LinkKind::Media, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_heading_slug() {
+        assert_eq!(heading_slug("Usage"), "usage");
+        assert_eq!(heading_slug("What's new in 1.0?"), "whats-new-in-10");
+        assert_eq!(heading_slug("`--check-http` flag"), "--check-http-flag");
+        assert_eq!(heading_slug("snake_case & more"), "snake_case--more");
+        assert_eq!(heading_slug("Ünïcode 🎉"), "ünïcode-");
+    }
+
+    #[test]
+    fn test_parse_anchors() {
+        let markdown = "# Usage\n\n## Usage\n\nSetext `code`\n---\n\n\
+            <a name=\"legacy\"></a>\n\n<h3 id=\"custom\">Custom</h3>\n\n# Usage\n";
+        let expected: HashSet<_> = [
+            "usage",
+            "usage-1",
+            "setext-code",
+            "legacy",
+            "custom",
+            "usage-2",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(parse_anchors(markdown), expected);
+    }
+}
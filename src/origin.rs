@@ -0,0 +1,455 @@
+//! Attributing links in rustdoc pages to the items and Rust source they were written in.
+//!
+//! rustdoc gives each member of an item an anchor like `method.bar`, and links each item and member
+//! to its source in `src/<crate>/<file>.rs.html#<start>-<end>`. A link belongs to the closest member
+//! and source link before it; its line is found by searching the doc comments of that source range.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use lol_html::{element, HtmlRewriter, Settings};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_derive::Serialize;
+use url::Url;
+
+use crate::fix::{is_doc_line, item_name};
+use crate::parse::{self, PageError};
+use crate::{CheckContext, CheckError, LinkError};
+
+/// Prefixes of the anchors rustdoc generates for the members of an item, e.g. `method.bar`.
+const MEMBER_KINDS: [&str; 6] = [
+    "associatedconstant",
+    "associatedtype",
+    "method",
+    "structfield",
+    "tymethod",
+    "variant",
+];
+
+/// The item whose documentation contains a link, and where it was written in the Rust source.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Origin {
+    /// The full path of the item, e.g. `my_crate::Foo::bar`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceLocation>,
+}
+
+/// A line in a Rust source file.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct SourceLocation {
+    /// The path to the file. This is only a real path if the source root of the crate is known
+    /// (see [`CheckContext::source_roots`]); otherwise it is relative to rustdoc's `src/` directory,
+    /// e.g. `my_crate/lib.rs`.
+    pub path: PathBuf,
+    /// The line number, starting at 1.
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line)
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.source, &self.item) {
+            (Some(source), Some(item)) => write!(f, "{} ({})", source, item),
+            (Some(source), None) => source.fmt(f),
+            (None, Some(item)) => f.write_str(item),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// The parts of a rustdoc page needed to attribute links to items.
+#[derive(Debug, Default)]
+struct PageAnchors {
+    /// The `data-root-path` of the page, i.e. the documentation root relative to the page.
+    root_path: Option<String>,
+    /// The members documented on the page, by the offset where they start.
+    /// Impl blocks are `None`, since they are not part of an item's path.
+    members: Vec<(usize, Option<String>)>,
+    /// Links to the source of the page's item and its members, by offset.
+    sources: Vec<(usize, String)>,
+    /// The target of each hyperlink and image, by offset, as written in the HTML.
+    hrefs: HashMap<usize, String>,
+}
+
+impl PageAnchors {
+    fn read(reader: impl Read) -> Result<Self, PageError> {
+        let anchors = RefCell::new(PageAnchors::default());
+        // See `parse::extract_page`: nothing is rewritten, so this is the offset of the current element.
+        let offset = Cell::new(0);
+
+        let mut rewriter = HtmlRewriter::new(
+            Settings {
+                element_content_handlers: vec![
+                    element!("[data-root-path]", |el| {
+                        let mut anchors = anchors.borrow_mut();
+                        if anchors.root_path.is_none() {
+                            anchors.root_path = el.get_attribute("data-root-path");
+                        }
+                        Ok(())
+                    }),
+                    element!("[id]", |el| {
+                        let id = el.get_attribute("id").unwrap();
+                        if let Some(member) = member(&id) {
+                            anchors.borrow_mut().members.push((offset.get(), member));
+                        }
+                        Ok(())
+                    }),
+                    element!("img[src]", |el| {
+                        let src = el.get_attribute("src").unwrap();
+                        anchors
+                            .borrow_mut()
+                            .hrefs
+                            .entry(offset.get())
+                            .or_insert(src);
+                        Ok(())
+                    }),
+                    element!("a[href]", |el| {
+                        let href = el.get_attribute("href").unwrap();
+                        let mut anchors = anchors.borrow_mut();
+                        let class = el.get_attribute("class").unwrap_or_default();
+                        if class
+                            .split_ascii_whitespace()
+                            .any(|class| class == "src" || class == "srclink")
+                        {
+                            anchors.sources.push((offset.get(), href.clone()));
+                        }
+                        anchors.hrefs.entry(offset.get()).or_insert(href);
+                        Ok(())
+                    }),
+                ],
+                ..Settings::default()
+            },
+            |chunk: &[u8]| offset.set(offset.get() + chunk.len()),
+        );
+        parse::read_html(reader, &mut |chunk| rewriter.write(chunk))?;
+        rewriter.end()?;
+
+        Ok(anchors.into_inner())
+    }
+}
+
+/// The name of the member an anchor belongs to, `Some(None)` for impl blocks,
+/// or `None` if the anchor is not a member at all.
+fn member(id: &str) -> Option<Option<String>> {
+    if id.starts_with("impl-") {
+        return Some(None);
+    }
+    let dot = id.find('.')?;
+    if !MEMBER_KINDS.contains(&&id[..dot]) {
+        return None;
+    }
+    let name = &id[dot + 1..];
+    // `variant.A.field.x` is the field of a variant; `method.into-1` disambiguates methods with the same name.
+    let name = name.split('.').next().unwrap();
+    let name = match name.rfind('-') {
+        Some(dash) if name[dash + 1..].bytes().all(|b| b.is_ascii_digit()) => &name[..dash],
+        _ => name,
+    };
+    Some(Some(name.to_owned()))
+}
+
+/// The path of the item documented on `page`, e.g. `my_crate::module::Foo` for `my_crate/module/struct.Foo.html`.
+fn page_item(page: &Path, doc_root: &Path) -> Option<String> {
+    let relative = page.strip_prefix(doc_root).ok()?;
+    let mut path = relative
+        .parent()?
+        .components()
+        .map(|c| c.as_os_str().to_str().map(str::to_owned))
+        .collect::<Option<Vec<_>>>()?;
+    if relative.file_name()? != "index.html" {
+        path.push(item_name(relative)?.to_owned());
+    }
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.join("::"))
+    }
+}
+
+/// Find the item and Rust source each error on the rustdoc page `path` was written in.
+///
+/// Pages which were not generated by rustdoc are left alone.
+pub(crate) fn attribute_errors(path: &Path, errors: &mut [LinkError], ctx: &CheckContext) {
+    // Pages which can't be read or parsed are reported as such, so there is nothing to attribute.
+    let anchors = match File::open(path)
+        .map_err(PageError::from)
+        .and_then(PageAnchors::read)
+    {
+        Ok(anchors) => anchors,
+        Err(_) => return,
+    };
+    let file_url = Url::from_file_path(path).unwrap();
+    let doc_root = match anchors
+        .root_path
+        .as_ref()
+        .and_then(|root| file_url.join(root).ok())
+        .and_then(|root| root.to_file_path().ok())
+    {
+        Some(root) => root,
+        None => return,
+    };
+    let page_item = page_item(path, &doc_root);
+    let mut source_files = HashMap::new();
+
+    for error in errors {
+        let offset = match error.position {
+            Some(pos) => pos.offset,
+            None => continue,
+        };
+        let needle = match &error.error {
+            // The source has backticks where rustdoc emitted `<code>`.
+            CheckError::IntraDocLink(text) => text
+                .strip_prefix("[<code>")
+                .and_then(|text| text.strip_suffix("</code>]"))
+                .map(|text| format!("`{}`", unescape(text))),
+            _ => anchors.hrefs.get(&offset).map(|href| unescape(href)),
+        };
+        // Other links, like scripts and stylesheets, are added by rustdoc itself.
+        let needle = match needle {
+            Some(needle) => needle,
+            None => continue,
+        };
+        let member = anchors
+            .members
+            .iter()
+            .take_while(|(start, _)| *start < offset)
+            .last()
+            .and_then(|(_, member)| member.as_ref());
+        let item = match (&page_item, member) {
+            (Some(item), Some(member)) => Some(format!("{}::{}", item, member)),
+            (item, _) => item.clone(),
+        };
+        let source = anchors
+            .sources
+            .iter()
+            .take_while(|(start, _)| *start < offset)
+            .last()
+            .and_then(|(_, href)| SourceRange::new(&file_url.join(href).ok()?, &doc_root, ctx))
+            .map(|range| range.find(&needle, &mut source_files));
+        if item.is_some() || source.is_some() {
+            error.origin = Some(Origin { item, source });
+        }
+    }
+}
+
+/// The lines of a Rust source file which contain an item, as linked from its documentation.
+struct SourceRange {
+    /// The rustdoc page showing the source.
+    page: PathBuf,
+    path: PathBuf,
+    start: usize,
+    end: usize,
+}
+
+impl SourceRange {
+    /// Parse a link to `<doc_root>/src/<crate>/<file>.rs.html#<start>-<end>`.
+    fn new(url: &Url, doc_root: &Path, ctx: &CheckContext) -> Option<Self> {
+        let (start, end) = match url.fragment() {
+            Some(fragment) => {
+                let mut lines = fragment.splitn(2, '-').map(str::parse);
+                let start = lines.next()?.ok()?;
+                (start, lines.next().unwrap_or(Ok(start)).ok()?)
+            }
+            None => (1, usize::MAX),
+        };
+        let mut page = url.clone();
+        page.set_fragment(None);
+        let page = page.to_file_path().ok()?;
+
+        let mut components = page
+            .strip_prefix(doc_root)
+            .ok()?
+            .components()
+            .map(|c| c.as_os_str().to_str());
+        if components.next()?? != "src" {
+            return None;
+        }
+        let krate = components.next()??;
+        let file = components.collect::<Option<Vec<_>>>()?.join("/");
+        let file = file.strip_suffix(".html")?;
+        let path = match ctx.source_roots.get(krate) {
+            Some(root) => root.join(file),
+            None => Path::new(krate).join(file),
+        };
+        Some(SourceRange {
+            page,
+            path,
+            start,
+            end,
+        })
+    }
+
+    /// Find the line containing `needle` in the doc comments of this range, or the start of the range otherwise.
+    ///
+    /// `source_files` caches the lines of each source page which has been read so far.
+    fn find(
+        self,
+        needle: &str,
+        source_files: &mut HashMap<PathBuf, Option<Vec<String>>>,
+    ) -> SourceLocation {
+        let page = &self.page;
+        let lines = source_files
+            .entry(page.clone())
+            .or_insert_with(|| source_lines(&std::fs::read_to_string(page).ok()?));
+        let line = lines
+            .as_ref()
+            .and_then(|lines| find_in_doc_comments(lines, self.start, self.end, needle));
+        SourceLocation {
+            path: self.path,
+            line: line.unwrap_or(self.start),
+        }
+    }
+}
+
+/// Find the line containing `needle` in the item spanning lines `start` to `end` of a source file,
+/// including its doc comments and attributes. Lines start at 1.
+pub(crate) fn find_in_doc_comments(
+    lines: &[String],
+    start: usize,
+    end: usize,
+    needle: &str,
+) -> Option<usize> {
+    // Outer doc comments and attributes come before the item, inner doc comments after its start.
+    let mut first = start.max(1);
+    while first > 1 && matches!(lines.get(first - 2), Some(line) if is_doc_or_attribute(line)) {
+        first -= 1;
+    }
+    let mut last = end.min(lines.len());
+    while matches!(lines.get(last), Some(line) if is_doc_or_attribute(line)) {
+        last += 1;
+    }
+    (first..=last).find(|&line| lines[line - 1].contains(needle))
+}
+
+fn is_doc_or_attribute(line: &str) -> bool {
+    is_doc_line(line) || line.trim_start().starts_with("#[")
+}
+
+/// The lines of the code shown on a rustdoc source page, as plain text.
+fn source_lines(html: &str) -> Option<Vec<String>> {
+    // Newer versions of rustdoc put the line numbers inline, older ones in a separate `<pre>`.
+    static LINE_NUMBER: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^<a [^>]*data-nosnippet[^>]*>\d+</a>").unwrap());
+    static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+    let code = &html[html.find(r#"<pre class="rust"#)?..];
+    let code = &code[code.find("<code")?..];
+    let code = &code[code.find('>')? + 1..code.find("</code>")?];
+    Some(
+        code.lines()
+            .map(|line| unescape(&TAG.replace_all(&LINE_NUMBER.replace(line, ""), "")))
+            .collect(),
+    )
+}
+
+fn unescape(html: &str) -> String {
+    html.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{member, page_item, source_lines, PageAnchors, SourceRange};
+    use crate::CheckContext;
+    use url::Url;
+
+    #[test]
+    fn test_members() {
+        assert_eq!(member("method.bar"), Some(Some("bar".into())));
+        assert_eq!(member("method.into-1"), Some(Some("into".into())));
+        assert_eq!(member("variant.A.field.x"), Some(Some("A".into())));
+        assert_eq!(member("impl-Send-for-Foo"), Some(None));
+        assert_eq!(member("examples"), None);
+        assert_eq!(member("fn.foo"), None);
+    }
+
+    #[test]
+    fn test_page_item() {
+        let root = Path::new("/doc");
+        let item = |page| page_item(Path::new(page), root);
+        assert_eq!(item("/doc/krate/index.html").as_deref(), Some("krate"));
+        assert_eq!(
+            item("/doc/krate/module/struct.Foo.html").as_deref(),
+            Some("krate::module::Foo")
+        );
+        assert_eq!(item("/doc/krate/all.html"), None);
+        assert_eq!(item("/doc/src/krate/lib.rs.html"), None);
+        assert_eq!(item("/elsewhere/krate/index.html"), None);
+    }
+
+    #[test]
+    fn test_page_anchors() {
+        let html = r#"<meta name="rustdoc-vars" data-root-path="../">
+<a class="src" href="../src/krate/lib.rs.html#4-7">source</a><a href="a.html">a</a>
+<section id="impl-Foo"><a class="src rightside" href="../src/krate/lib.rs.html#9-12">source</a></section>
+<section id="method.bar"><a class="srclink" href="../src/krate/lib.rs.html#11">source</a></section>"#;
+        let anchors = PageAnchors::read(html.as_bytes()).unwrap();
+        assert_eq!(anchors.root_path.as_deref(), Some("../"));
+        let members: Vec<_> = anchors.members.iter().map(|(_, m)| m.clone()).collect();
+        assert_eq!(members, [None, Some("bar".to_owned())]);
+        let sources: Vec<_> = anchors.sources.iter().map(|(_, s)| s.as_str()).collect();
+        assert_eq!(
+            sources,
+            [
+                "../src/krate/lib.rs.html#4-7",
+                "../src/krate/lib.rs.html#9-12",
+                "../src/krate/lib.rs.html#11",
+            ]
+        );
+        let (offset, _) = anchors.sources[0];
+        assert!(html[offset..].starts_with("<a class=\"src\""));
+        assert_eq!(anchors.hrefs.len(), 4);
+    }
+
+    #[test]
+    fn test_source_lines() {
+        let new = r#"<pre class="rust"><code><a href=#1 id=1 data-nosnippet>1</a><span class="doccomment">//! [a](a.html?x=1&amp;y=2)
+<a href=#2 id=2 data-nosnippet>2</a></span><span class="kw">pub fn </span>foo() {}</code></pre>"#;
+        assert_eq!(
+            source_lines(new).unwrap(),
+            ["//! [a](a.html?x=1&y=2)", "pub fn foo() {}"]
+        );
+        let old = r#"<pre class="line-numbers"><span id="1">1</span>
+<span id="2">2</span></pre><pre class="rust"><code><span class="doccomment">//! a</span>
+<span class="kw">pub fn</span> <span class="ident">foo</span>() {}</code></pre>"#;
+        assert_eq!(source_lines(old).unwrap(), ["//! a", "pub fn foo() {}"]);
+    }
+
+    #[test]
+    fn test_source_range() {
+        let mut ctx = CheckContext::default();
+        let url = Url::parse("file:///doc/src/krate/module/mod.rs.html#10-20").unwrap();
+        let range = SourceRange::new(&url, Path::new("/doc"), &ctx).unwrap();
+        assert_eq!(range.page, Path::new("/doc/src/krate/module/mod.rs.html"));
+        assert_eq!(range.path, Path::new("krate/module/mod.rs"));
+        assert_eq!((range.start, range.end), (10, 20));
+
+        ctx.source_roots
+            .insert("krate".into(), PathBuf::from("/home/user/krate/src"));
+        let url = Url::parse("file:///doc/src/krate/lib.rs.html#3").unwrap();
+        let range = SourceRange::new(&url, Path::new("/doc"), &ctx).unwrap();
+        assert_eq!(range.path, Path::new("/home/user/krate/src/lib.rs"));
+        assert_eq!((range.start, range.end), (3, 3));
+
+        let url = Url::parse("file:///doc/krate/index.html").unwrap();
+        assert!(SourceRange::new(&url, Path::new("/doc"), &ctx).is_none());
+    }
+}
@@ -1,106 +1,724 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
 
+use encoding_rs::{Decoder, Encoding, UTF_8};
 use log::debug;
-use lol_html::{element, RewriteStrSettings};
+use lol_html::errors::RewritingError;
+use lol_html::{element, HtmlRewriter, Settings};
 use once_cell::sync::Lazy;
-use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
-use crate::CheckError;
+use crate::{CheckError, LinkError, Severity};
 
-/// Return all broken intra-doc links in the source (of the form ``[`x`]``),
-/// which presumably should have been resolved by rustdoc.
-pub fn broken_intra_doc_links(html: &str) -> Vec<CheckError> {
-    static BROKEN_INTRA_DOC_LINK: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r#"\[<code>(.*)</code>\]"#).unwrap());
-    BROKEN_INTRA_DOC_LINK
-        .captures_iter(html)
-        .map(|captures| CheckError::IntraDocLink(captures.get(0).unwrap().as_str().to_owned()))
-        .collect()
+/// A location in an HTML or Markdown file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Position {
+    /// The number of bytes from the start of the file, after decoding it to UTF-8.
+    ///
+    /// This is only an offset into the file itself if it is encoded as UTF-8. Files in other encodings, declared
+    /// with `<meta charset>` or a byte order mark, are counted in the bytes of their UTF-8 text instead;
+    /// `line` and `column` are the same either way.
+    pub offset: usize,
+    /// The line number, starting at 1.
+    pub line: usize,
+    /// The column in characters, starting at 1.
+    pub column: usize,
+}
+
+impl Position {
+    const START: Position = Position {
+        offset: 0,
+        line: 1,
+        column: 1,
+    };
+
+    /// The position after `text`, if it starts at this position.
+    ///
+    /// `text` must be UTF-8, but it may start or end in the middle of a character.
+    fn advance(self, text: &[u8]) -> Position {
+        // Count the bytes which start a character, i.e. everything but continuation bytes.
+        let chars = |bytes: &[u8]| bytes.iter().filter(|&&b| b & 0xc0 != 0x80).count();
+        let offset = self.offset + text.len();
+        match text.iter().rposition(|&b| b == b'\n') {
+            Some(last) => Position {
+                offset,
+                line: self.line + text.iter().filter(|&&b| b == b'\n').count(),
+                column: chars(&text[last + 1..]) + 1,
+            },
+            None => Position {
+                offset,
+                column: self.column + chars(text),
+                ..self
+            },
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Converts byte offsets in a file to line and column numbers.
+pub(crate) struct LineIndex<'a> {
+    text: &'a str,
+    /// The byte offset of the start of each line.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { text, line_starts }
+    }
+
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        let line_start = self.line_starts[line];
+        let column = self
+            .text
+            .get(line_start..offset)
+            .map_or(offset - line_start, |s| s.chars().count());
+        Position {
+            offset,
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+}
+
+/// The kind of element a link was found on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    /// `<a href>` and `<area href>`
+    Hyperlink,
+    /// `<img>`, `<source>`, `<video>`, `<audio>` and `<track>`
+    Media,
+    /// `<link href>`, e.g. stylesheets and favicons
+    Resource,
+    /// `<script src>`
+    Script,
+    /// `<iframe src>`, `<embed src>` and `<object data>`
+    Embed,
+    /// `<form action>`
+    Form,
+}
+
+impl LinkKind {
+    /// All kinds of links deadlinks knows how to extract.
+    pub const ALL: [LinkKind; 6] = [
+        LinkKind::Hyperlink,
+        LinkKind::Media,
+        LinkKind::Resource,
+        LinkKind::Script,
+        LinkKind::Embed,
+        LinkKind::Form,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LinkKind::Hyperlink => "hyperlink",
+            LinkKind::Media => "media",
+            LinkKind::Resource => "resource",
+            LinkKind::Script => "script",
+            LinkKind::Embed => "embed",
+            LinkKind::Form => "form",
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LinkKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("unknown link kind '{}'", s))
+    }
+}
+
+/// Every `(element, attribute)` pair that can contain a URL.
+const URL_ATTRIBUTES: [(&str, &str, LinkKind); 16] = [
+    ("a", "href", LinkKind::Hyperlink),
+    ("area", "href", LinkKind::Hyperlink),
+    ("img", "src", LinkKind::Media),
+    ("img", "srcset", LinkKind::Media),
+    ("source", "src", LinkKind::Media),
+    ("source", "srcset", LinkKind::Media),
+    ("video", "src", LinkKind::Media),
+    ("video", "poster", LinkKind::Media),
+    ("audio", "src", LinkKind::Media),
+    ("track", "src", LinkKind::Media),
+    ("link", "href", LinkKind::Resource),
+    ("script", "src", LinkKind::Script),
+    ("iframe", "src", LinkKind::Embed),
+    ("embed", "src", LinkKind::Embed),
+    ("object", "data", LinkKind::Embed),
+    ("form", "action", LinkKind::Form),
+];
+
+/// A link found in an HTML file, along with where it came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedLink {
+    pub url: Url,
+    pub element: &'static str,
+    pub attribute: &'static str,
+    pub kind: LinkKind,
+    /// The position of the start of the element.
+    pub position: Position,
+}
+
+/// Everything deadlinks needs from an HTML page, collected in a single pass over it.
+#[derive(Debug, Default)]
+pub(crate) struct PageSummary {
+    /// The links on the page, if they were asked for; see [`parse_links`].
+    pub(crate) links: Vec<ParsedLink>,
+    /// The broken intra-doc links on the page, if they were asked for; see [`IntraDocLinks`].
+    pub(crate) broken_intra_doc_links: Vec<LinkError>,
+    /// The `id` attributes of all elements, and the `name` attributes of `<a>` elements.
+    pub(crate) anchors: HashSet<String>,
+    /// The URL of the first `<meta http-equiv="refresh">` tag, which is the one browsers follow.
+    pub(crate) redirect: Option<String>,
+}
+
+/// Why a page could not be read.
+#[derive(Debug)]
+pub(crate) enum PageError {
+    /// The page could not be read, or is not valid in its encoding.
+    Read(io::Error),
+    /// The HTML is too ambiguous to parse without building the whole document tree.
+    Parse(RewritingError),
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PageError::Read(err) => err.fmt(f),
+            PageError::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<io::Error> for PageError {
+    fn from(err: io::Error) -> Self {
+        PageError::Read(err)
+    }
+}
+
+impl From<RewritingError> for PageError {
+    fn from(err: RewritingError) -> Self {
+        PageError::Parse(err)
+    }
+}
+
+/// What the links on a page are resolved against; see [`parse_links`].
+pub(crate) struct LinkBase<'a> {
+    pub(crate) root_url: &'a Url,
+    pub(crate) site_root: &'a str,
+    pub(crate) base_url: Option<&'a Url>,
+    pub(crate) file_url: &'a Url,
+}
+
+impl LinkBase<'_> {
+    /// The URL `href` points to, or `None` if it is outside of the site root or can't be parsed.
+    fn resolve(&self, href: &str) -> Option<Url> {
+        let same_site = self
+            .base_url
+            .and_then(|base_url| same_site_path(href, base_url, self.site_root));
+        let href = same_site.as_deref().unwrap_or(href);
+        // base is the file path, unless path is absolute (starts with /)
+        let (base, href) = if href.starts_with('/') {
+            // Treat absolute paths as absolute with respect to the `root_url`, not with respect to the file system.
+            match strip_site_root(href, self.site_root) {
+                Some(relative) => (self.root_url, relative),
+                None => {
+                    debug!(
+                        "skipping {:?} outside of the site root {:?}",
+                        href, self.site_root
+                    );
+                    return None;
+                }
+            }
+        } else {
+            (self.file_url, href)
+        };
+
+        match base.join(href) {
+            Ok(url) => {
+                debug!("link is {:?}", url);
+                Some(url)
+            }
+            Err(_) => {
+                debug!("unparsable link {:?}", href);
+                None
+            }
+        }
+    }
 }
 
 /// Return all links in the HTML file, whether or not they are broken.
 ///
+/// Each URL is only returned once for each [`LinkKind`], for the first element of that kind it was found on.
+///
 /// `root_url` is a fixed path relative to the documentation directory. For `target/doc/crate_x/y`, it's `crate_x`.
 /// `file_url` is the file path relative to the documentation directory; it's different for each file.
 /// For `target/doc/crate_x/y`, it's `crate_x/y`.
 /// In general, `file_url.starts_with(root_url)` should always be true.
-pub fn parse_a_hrefs(html: &str, root_url: &Url, file_url: &Url) -> HashSet<Url> {
-    let mut urls = HashSet::new();
-    lol_html::rewrite_str(
-        html,
-        RewriteStrSettings {
-            element_content_handlers: vec![element!("a[href]", |el| {
-                let href = el.get_attribute("href").unwrap();
-                // base is the file path, unless path is absolute (starts with /)
-                let (base, href) = if let Some(absolute) = href.strip_prefix('/') {
-                    // Treat absolute paths as absolute with respect to the `root_url`, not with respect to the file system.
-                    (&root_url, absolute)
-                } else {
-                    (&file_url, href.as_str())
-                };
-
-                if let Ok(link) = base.join(href) {
-                    debug!("link is {:?}", link);
-                    urls.insert(link);
-                } else {
-                    debug!("unparsable link {:?}", href);
+/// `site_root` is the path `root_url` is served under, e.g. `/` or `/my-book/`. Absolute paths
+/// are resolved relative to `root_url` after removing it; absolute paths outside of it are skipped.
+/// Full URLs on the same site as `base_url` are resolved like absolute paths, as long as they are under `site_root`.
+///
+/// Returns an error if the HTML is too ambiguous to parse without building the whole document tree.
+pub fn parse_links(
+    html: &str,
+    root_url: &Url,
+    site_root: &str,
+    base_url: Option<&Url>,
+    file_url: &Url,
+) -> Result<Vec<ParsedLink>, PageError> {
+    let base = LinkBase {
+        root_url,
+        site_root,
+        base_url,
+        file_url,
+    };
+    let page = extract(Some(&base), false, |write| Ok(write(html.as_bytes())?))?;
+    Ok(page.links)
+}
+
+/// Read an HTML page from `reader` and collect its anchors and redirect, along with its links if `links` is set,
+/// and its broken intra-doc links if `intra_doc_links` is set.
+///
+/// The page is parsed while it is being read, so only a small part of it is in memory at any time.
+/// It is decoded like [`read_html`] does.
+pub(crate) fn extract_page(
+    reader: impl Read,
+    links: Option<&LinkBase>,
+    intra_doc_links: bool,
+) -> Result<PageSummary, PageError> {
+    extract(links, intra_doc_links, |write| read_html(reader, write))
+}
+
+/// Parse the UTF-8 HTML `feed` passes to the function it is given, collecting everything [`extract_page`] does.
+fn extract(
+    links: Option<&LinkBase>,
+    intra_doc_links: bool,
+    feed: impl FnOnce(&mut dyn FnMut(&[u8]) -> Result<(), RewritingError>) -> Result<(), PageError>,
+) -> Result<PageSummary, PageError> {
+    let page = RefCell::new(PageSummary::default());
+    let seen = RefCell::new(HashSet::new());
+    let mut intra_doc_links = if intra_doc_links {
+        Some(IntraDocLinks::new())
+    } else {
+        None
+    };
+    // Nothing is rewritten, so the output is identical to the input
+    // and the position of the end of the output so far is the position of the current element.
+    let position = Cell::new(Position::START);
+
+    let add_link = |url: Url, element, attribute, kind| {
+        if seen.borrow_mut().insert((url.clone(), kind)) {
+            page.borrow_mut().links.push(ParsedLink {
+                url,
+                element,
+                attribute,
+                kind,
+                position: position.get(),
+            });
+        }
+    };
+
+    let mut handlers = vec![
+        element!("*[id]", |el| {
+            let id = el.get_attribute("id").unwrap();
+            page.borrow_mut().anchors.insert(id);
+            Ok(())
+        }),
+        element!("a[name]", |el| {
+            let name = el.get_attribute("name").unwrap();
+            page.borrow_mut().anchors.insert(name);
+            Ok(())
+        }),
+        element!(r#"head > meta[http-equiv="refresh"]"#, |el| {
+            let mut page = page.borrow_mut();
+            if page.redirect.is_none() {
+                let content = el.get_attribute("content").unwrap_or_default();
+                page.redirect = content.split("URL=").nth(1).map(|s| s.to_owned());
+            } else {
+                debug!("ignoring all but the first `http-equiv` meta tag");
+            }
+            Ok(())
+        }),
+    ];
+    if let Some(base) = links {
+        handlers.extend(URL_ATTRIBUTES.iter().map(|&(element, attribute, kind)| {
+            let add_link = &add_link;
+            element!(format!("{}[{}]", element, attribute), move |el| {
+                // `<link rel="preconnect">` and friends point at origins, not documents
+                if element == "link"
+                    && is_resource_hint(&el.get_attribute("rel").unwrap_or_default())
+                {
+                    return Ok(());
+                }
+                let value = el.get_attribute(attribute).unwrap();
+                if attribute == "srcset" {
+                    for candidate in parse_srcset(&value) {
+                        if let Some(url) = base.resolve(candidate) {
+                            add_link(url, element, attribute, kind);
+                        }
+                    }
+                } else if let Some(url) = base.resolve(value.trim()) {
+                    add_link(url, element, attribute, kind);
                 }
                 Ok(())
-            })],
-            ..RewriteStrSettings::default()
+            })
+        }));
+    }
+
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: handlers,
+            ..Settings::default()
+        },
+        |chunk: &[u8]| {
+            if let Some(intra_doc_links) = &mut intra_doc_links {
+                intra_doc_links.write(chunk);
+            }
+            position.set(position.get().advance(chunk));
         },
-    )
-    .expect("html rewriting failed");
+    );
+    feed(&mut |chunk| rewriter.write(chunk))?;
+    rewriter.end()?;
 
-    urls
+    let mut page = page.into_inner();
+    if let Some(intra_doc_links) = intra_doc_links {
+        page.broken_intra_doc_links = intra_doc_links.finish();
+    }
+    Ok(page)
 }
 
-/// Parses the given string as HTML and returns values of all element's id attributes
-pub(crate) fn parse_fragments(html: &str) -> HashSet<String> {
-    let mut fragments = HashSet::new();
-    lol_html::rewrite_str(
-        html,
-        RewriteStrSettings {
-            element_content_handlers: vec![element!("*[id]", |el| {
-                let id = el.get_attribute("id").unwrap();
-                fragments.insert(id);
-                Ok(())
-            })],
-            ..RewriteStrSettings::default()
-        },
-    )
-    .expect("html rewriting failed");
-
-    fragments
-}
-
-pub(crate) fn parse_redirect(html: &str) -> Option<String> {
-    let mut url = None;
-    lol_html::rewrite_str(
-        html,
-        RewriteStrSettings {
-            element_content_handlers: vec![element!(
-                r#"head > meta[http-equiv="refresh"]"#,
-                |el| {
-                    let content = el.get_attribute("content").unwrap();
-                    assert!(url.is_none(), "multiple `http-equiv` meta tags");
-                    url = content.split("URL=").nth(1).map(|s| s.to_owned());
-                    Ok(())
+/// Finds broken intra-doc links (of the form ``[`x`]``) in a page while it is being read,
+/// which presumably should have been resolved by rustdoc.
+///
+/// The links can't span lines, so only the current line is kept in memory.
+struct IntraDocLinks {
+    line: Vec<u8>,
+    /// The position of the start of `line`.
+    start: Position,
+    errors: Vec<LinkError>,
+}
+
+impl IntraDocLinks {
+    fn new() -> Self {
+        IntraDocLinks {
+            line: Vec::new(),
+            start: Position::START,
+            errors: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, mut chunk: &[u8]) {
+        while let Some(newline) = chunk.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&chunk[..newline]);
+            self.end_line();
+            chunk = &chunk[newline + 1..];
+        }
+        self.line.extend_from_slice(chunk);
+    }
+
+    fn end_line(&mut self) {
+        static BROKEN_INTRA_DOC_LINK: Lazy<regex::bytes::Regex> =
+            Lazy::new(|| regex::bytes::Regex::new(r#"\[<code>(.*)</code>\]"#).unwrap());
+        for m in BROKEN_INTRA_DOC_LINK.find_iter(&self.line) {
+            self.errors.push(LinkError {
+                position: Some(self.start.advance(&self.line[..m.start()])),
+                severity: Severity::Error,
+                origin: None,
+                error: CheckError::IntraDocLink(String::from_utf8_lossy(m.as_bytes()).into_owned()),
+            });
+        }
+        self.start = self.start.advance(&self.line).advance(b"\n");
+        self.line.clear();
+    }
+
+    fn finish(mut self) -> Vec<LinkError> {
+        self.end_line();
+        self.errors
+    }
+}
+
+/// The part of the absolute path `href` after `site_root`, or `None` if it is outside of `site_root`.
+fn strip_site_root<'a>(href: &'a str, site_root: &str) -> Option<&'a str> {
+    let site_root = site_root.trim_end_matches('/');
+    match href.strip_prefix(site_root)? {
+        "" => Some(""),
+        rest => rest.strip_prefix('/'),
+    }
+}
+
+/// The absolute path (with query and fragment) of `href`, if it is a full URL on the same site as `base_url`
+/// and under `site_root`.
+fn same_site_path(href: &str, base_url: &Url, site_root: &str) -> Option<String> {
+    let url = Url::parse(href).ok()?;
+    if url.origin() != base_url.origin() {
+        return None;
+    }
+    strip_site_root(url.path(), site_root)?;
+    Some(url[url::Position::BeforePath..].to_owned())
+}
+
+fn is_resource_hint(rel: &str) -> bool {
+    rel.split_ascii_whitespace().any(|rel| {
+        ["preconnect", "dns-prefetch"]
+            .iter()
+            .any(|hint| rel.eq_ignore_ascii_case(hint))
+    })
+}
+
+/// Return the URLs of all image candidates in a `srcset` attribute.
+///
+/// See <https://html.spec.whatwg.org/multipage/images.html#parsing-a-srcset-attribute>.
+fn parse_srcset(srcset: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return urls;
+        }
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (url, after) = rest.split_at(end);
+        if url.ends_with(',') {
+            // A URL directly followed by a comma has no descriptors.
+            urls.push(url.trim_end_matches(','));
+            rest = after;
+        } else {
+            urls.push(url);
+            // Skip the descriptors, e.g. `2x` or `100w`.
+            rest = after.find(',').map_or("", |comma| &after[comma..]);
+        }
+    }
+}
+
+/// The size of the chunks HTML files are read in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Like browsers, only look for `<meta charset>` near the start of the file.
+const PRESCAN_SIZE: u64 = 1024;
+
+/// Read an HTML file in chunks and pass them to `write` as UTF-8.
+///
+/// The file is decoded as UTF-8 unless it starts with a byte order mark, or declares a different encoding
+/// with `<meta charset>` near its start. Files which are not UTF-8 and don't declare their encoding
+/// give an `InvalidData` error.
+pub(crate) fn read_html(
+    mut reader: impl Read,
+    write: &mut dyn FnMut(&[u8]) -> Result<(), RewritingError>,
+) -> Result<(), PageError> {
+    let mut start = Vec::new();
+    reader.by_ref().take(PRESCAN_SIZE).read_to_end(&mut start)?;
+    let mut decoder = HtmlDecoder::new(&start);
+    decoder.decode(&start, false, write)?;
+
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        decoder.decode(&chunk[..len], false, write)?;
+    }
+    decoder.decode(&[], true, write)
+}
+
+/// Converts the chunks of an HTML file to UTF-8, or checks that they already are.
+enum HtmlDecoder {
+    Utf8 {
+        /// The bytes at the end of the last chunk which start a character that continues in the next one.
+        partial: Vec<u8>,
+        /// Why the file should have been UTF-8, in case it isn't.
+        invalid: String,
+    },
+    Other(Decoder),
+}
+
+impl HtmlDecoder {
+    /// Detect the encoding of a file from its first [`PRESCAN_SIZE`] bytes.
+    fn new(start: &[u8]) -> Self {
+        let utf8 = |invalid: String| HtmlDecoder::Utf8 {
+            partial: Vec::new(),
+            invalid,
+        };
+        let declared_utf8 =
+            || utf8("the file is not valid UTF-8, even though it declares it is".to_owned());
+        if let Some((encoding, _)) = Encoding::for_bom(start) {
+            return if encoding == UTF_8 {
+                declared_utf8()
+            } else {
+                HtmlDecoder::Other(encoding.new_decoder_with_bom_removal())
+            };
+        }
+        let label = match meta_charset(start) {
+            Some(label) => label,
+            None => {
+                return utf8(
+                    "the file is not valid UTF-8, and does not declare its encoding with <meta charset>"
+                        .to_owned(),
+                )
+            }
+        };
+        match Encoding::for_label(label) {
+            // Browsers ignore UTF-16 in `<meta charset>`, since the declaration itself wouldn't be readable.
+            Some(encoding) if encoding.output_encoding() == UTF_8 => declared_utf8(),
+            Some(encoding) => HtmlDecoder::Other(encoding.new_decoder_without_bom_handling()),
+            None => utf8(format!(
+                "the file is not valid UTF-8, and declares an unknown encoding {:?} with <meta charset>",
+                String::from_utf8_lossy(label)
+            )),
+        }
+    }
+
+    fn decode(
+        &mut self,
+        chunk: &[u8],
+        last: bool,
+        write: &mut dyn FnMut(&[u8]) -> Result<(), RewritingError>,
+    ) -> Result<(), PageError> {
+        match self {
+            HtmlDecoder::Utf8 { partial, invalid } => {
+                if !is_valid_utf8(partial, chunk) || (last && !partial.is_empty()) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, invalid.clone()).into());
                 }
-            )],
-            ..RewriteStrSettings::default()
-        },
-    )
-    .expect("invalid HTML");
-    url
+                write(chunk)?;
+            }
+            HtmlDecoder::Other(decoder) => {
+                let mut html = String::with_capacity(
+                    decoder
+                        .max_utf8_buffer_length(chunk.len())
+                        .expect("chunks are small"),
+                );
+                let (_, _, had_errors) = decoder.decode_to_string(chunk, &mut html, last);
+                if had_errors {
+                    debug!(
+                        "replaced characters which are invalid in {}",
+                        decoder.encoding().name()
+                    );
+                }
+                write(html.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `chunk` is valid UTF-8, when it directly follows the bytes in `partial`.
+///
+/// Afterwards, `partial` contains the start of the last character in `chunk` if it is incomplete.
+fn is_valid_utf8(partial: &mut Vec<u8>, mut chunk: &[u8]) -> bool {
+    if !partial.is_empty() {
+        let width = match partial[0] {
+            0xf0..=0xf7 => 4,
+            0xe0..=0xef => 3,
+            _ => 2,
+        };
+        let needed = (width - partial.len()).min(chunk.len());
+        partial.extend_from_slice(&chunk[..needed]);
+        chunk = &chunk[needed..];
+        if partial.len() < width {
+            return true;
+        }
+        if std::str::from_utf8(partial).is_err() {
+            return false;
+        }
+        partial.clear();
+    }
+    match std::str::from_utf8(chunk) {
+        Ok(_) => true,
+        Err(err) if err.error_len().is_none() => {
+            partial.extend_from_slice(&chunk[err.valid_up_to()..]);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// The encoding declared by `<meta charset="...">` or `<meta http-equiv="Content-Type" content="...; charset=...">`.
+fn meta_charset(html: &[u8]) -> Option<&[u8]> {
+    static META_CHARSET: Lazy<regex::bytes::Regex> = Lazy::new(|| {
+        regex::bytes::Regex::new(r#"(?i-u)<meta\s[^>]*?charset\s*=\s*["']?\s*([a-z0-9_:.\-]+)"#)
+            .unwrap()
+    });
+    META_CHARSET
+        .captures(html)
+        .and_then(|captures| captures.get(1))
+        .map(|label| label.as_bytes())
 }
 
 #[cfg(test)]
 mod test {
-    use super::{parse_a_hrefs, parse_fragments};
+    use std::io::{self, Read};
+
+    use super::{
+        extract_page, parse_links, parse_srcset, read_html, LinkKind, PageError, Position,
+    };
     use url::Url;
 
+    /// A reader which returns one byte at a time, to split the file in as many chunks as possible.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn decode(html: &[u8]) -> Result<String, PageError> {
+        let mut decoded = Vec::new();
+        read_html(Trickle(html), &mut |chunk| {
+            decoded.extend_from_slice(chunk);
+            Ok(())
+        })?;
+        Ok(String::from_utf8(decoded).unwrap())
+    }
+
+    fn parse_urls(html: &str, root: &str, file: &str) -> Vec<Url> {
+        parse_links(
+            html,
+            &Url::from_directory_path(root).unwrap(),
+            "/",
+            None,
+            &Url::from_file_path(file).unwrap(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|link| link.url)
+        .collect()
+    }
+
     #[test]
     fn test_parse_a_hrefs() {
         let html = r#"
@@ -112,11 +730,7 @@ mod test {
             </body>
         </html>"#;
 
-        let urls = parse_a_hrefs(
-            html,
-            &Url::from_directory_path("/base").unwrap(),
-            &Url::from_file_path("/base/test.html").unwrap(),
-        );
+        let urls = parse_urls(html, "/base", "/base/test.html");
 
         assert!(urls.contains(&Url::from_file_path("/base/a.html").unwrap()));
         assert!(urls.contains(&Url::from_file_path("/base/b/c.html").unwrap()));
@@ -134,31 +748,334 @@ mod test {
             </body>
         </html>"#;
 
-        let urls = parse_a_hrefs(
+        let urls = parse_urls(html, "/root", "/root/base/test.html");
+
+        assert!(urls.contains(&Url::from_file_path("/root/base/a.html").unwrap()));
+        assert!(urls.contains(&Url::from_file_path("/root/b/c.html").unwrap()));
+        assert!(urls.contains(&Url::from_file_path("/root/d.html").unwrap()));
+    }
+
+    #[test]
+    fn test_parse_a_hrefs_under_site_root() {
+        let html = r#"
+        <a href="/my-book/a.html">a</a>
+        <a href="/my-book">index</a>
+        <a href="/other-book/b.html">b</a>
+        <a href="/my-bookcase.html">c</a>"#;
+
+        let urls: Vec<_> = parse_links(
             html,
             &Url::from_directory_path("/root").unwrap(),
+            "/my-book/",
+            None,
             &Url::from_file_path("/root/base/test.html").unwrap(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|link| link.url)
+        .collect();
+
+        assert_eq!(
+            urls,
+            [
+                Url::from_file_path("/root/a.html").unwrap(),
+                Url::from_directory_path("/root").unwrap(),
+            ]
         );
+    }
 
-        assert!(urls.contains(&Url::from_file_path("/root/base/a.html").unwrap()));
-        assert!(urls.contains(&Url::from_file_path("/root/b/c.html").unwrap()));
-        assert!(urls.contains(&Url::from_file_path("/root/d.html").unwrap()));
+    #[test]
+    fn test_parse_same_site_urls() {
+        let html = r#"
+        <a href="https://example.com/docs/a.html?q=1#f">a</a>
+        <a href="/docs/b.html">b</a>
+        <a href="https://example.com/blog/">blog</a>
+        <a href="http://example.com/docs/c.html">other scheme</a>"#;
+
+        let base_url = Url::parse("https://example.com/docs/").unwrap();
+        let urls: Vec<_> = parse_links(
+            html,
+            &Url::from_directory_path("/root").unwrap(),
+            base_url.path(),
+            Some(&base_url),
+            &Url::from_file_path("/root/base/test.html").unwrap(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|link| link.url.to_string())
+        .collect();
+
+        assert_eq!(
+            urls,
+            [
+                "file:///root/a.html?q=1#f",
+                "file:///root/b.html",
+                "https://example.com/blog/",
+                "http://example.com/docs/c.html",
+            ]
+        );
     }
 
     #[test]
-    fn test_parse_fragments() {
+    fn test_parse_all_link_kinds() {
+        let html = r#"
+        <!DOCTYPE html>
+        <html>
+            <head>
+                <link rel="stylesheet" href="style.css">
+                <link rel="preconnect" href="https://fonts.example.com">
+                <script src="main.js"></script>
+            </head>
+            <body>
+                <img src="logo.png" srcset="logo-2x.png 2x, logo-3x.png 3x">
+                <iframe src="frame.html"></iframe>
+                <map><area href="area.html"></map>
+                <form action="search.html"></form>
+                <a href="logo.png">same as the image</a>
+            </body>
+        </html>"#;
+
+        let links = parse_links(
+            html,
+            &Url::from_directory_path("/base").unwrap(),
+            "/",
+            None,
+            &Url::from_file_path("/base/test.html").unwrap(),
+        )
+        .unwrap();
+        let found: Vec<_> = links
+            .iter()
+            .map(|link| {
+                let path = link.url.to_file_path().unwrap();
+                let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+                (name, link.element, link.attribute, link.kind)
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                ("style.css".into(), "link", "href", LinkKind::Resource),
+                ("main.js".into(), "script", "src", LinkKind::Script),
+                ("logo.png".into(), "img", "src", LinkKind::Media),
+                ("logo-2x.png".into(), "img", "srcset", LinkKind::Media),
+                ("logo-3x.png".into(), "img", "srcset", LinkKind::Media),
+                ("frame.html".into(), "iframe", "src", LinkKind::Embed),
+                ("area.html".into(), "area", "href", LinkKind::Hyperlink),
+                ("search.html".into(), "form", "action", LinkKind::Form),
+                ("logo.png".into(), "a", "href", LinkKind::Hyperlink),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_positions() {
+        let html =
+            "<html>\n<body>\n  <p>héllo <a href=\"a.html\">a</a></p>\n<img\n src=\"b.png\"></body>";
+        let links = parse_links(
+            html,
+            &Url::from_directory_path("/base").unwrap(),
+            "/",
+            None,
+            &Url::from_file_path("/base/test.html").unwrap(),
+        )
+        .unwrap();
+        let positions: Vec<_> = links.iter().map(|link| link.position).collect();
+        assert_eq!(
+            positions,
+            [
+                Position {
+                    offset: 26,
+                    line: 3,
+                    column: 12
+                },
+                Position {
+                    offset: 53,
+                    line: 4,
+                    column: 1
+                },
+            ]
+        );
+        assert!(html[26..].starts_with("<a href"));
+        assert!(html[53..].starts_with("<img"));
+    }
+
+    #[test]
+    fn test_intra_doc_link_positions() {
+        let html = "<p>a</p>\n<p>See [<code>Foo</code>]</p>\n<p>[<code>Bar</code>]";
+        let page = extract_page(Trickle(html.as_bytes()), None, true).unwrap();
+        let errors: Vec<_> = page
+            .broken_intra_doc_links
+            .iter()
+            .map(|err| (err.error.to_string(), err.position.unwrap()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "Broken intra-doc link to [<code>Foo</code>]!".to_owned(),
+                    Position {
+                        offset: 16,
+                        line: 2,
+                        column: 8
+                    }
+                ),
+                (
+                    "Broken intra-doc link to [<code>Bar</code>]!".to_owned(),
+                    Position {
+                        offset: 42,
+                        line: 3,
+                        column: 4
+                    }
+                ),
+            ]
+        );
+        assert!(extract_page(html.as_bytes(), None, false)
+            .unwrap()
+            .broken_intra_doc_links
+            .is_empty());
+    }
+
+    #[test]
+    fn test_parse_srcset() {
+        assert_eq!(parse_srcset("a.png"), ["a.png"]);
+        assert_eq!(parse_srcset("a.png 1x, b.png 2x"), ["a.png", "b.png"]);
+        assert_eq!(parse_srcset("a.png,b.png 100w"), ["a.png,b.png"]);
+        assert_eq!(
+            parse_srcset(" a.png, b.png ,c.png"),
+            ["a.png", "b.png", "c.png"]
+        );
+        assert_eq!(parse_srcset("a,b.png 1x,c.png"), ["a,b.png", "c.png"]);
+        assert!(parse_srcset(" , ").is_empty());
+    }
+
+    #[test]
+    fn test_parse_anchors() {
         let html = r#"
         <!DOCTYPE html>
         <html>
             <body>
                 <a id="a">a</a>
                 <h1 id="h1">h1</h1>
+                <a name="legacy"></a>
+                <div name="not-an-anchor"></div>
             </body>
         </html>"#;
 
-        let fragments = parse_fragments(html);
+        let anchors = extract_page(html.as_bytes(), None, false).unwrap().anchors;
+
+        assert!(anchors.contains("a"));
+        assert!(anchors.contains("h1"));
+        assert!(anchors.contains("legacy"));
+        assert!(!anchors.contains("not-an-anchor"));
+    }
+
+    #[test]
+    fn test_extract_page_in_chunks() {
+        let html = "<html><head><meta http-equiv=\"refresh\" content=\"0;URL=../other.html\"></head>\n\
+            <body><p id=\"caf\u{e9}\">caf\u{e9} <a href=\"caf\u{e9}.html\">\u{2615}</a></p></body></html>";
+        let root = Url::from_directory_path("/base").unwrap();
+        let file = Url::from_file_path("/base/test.html").unwrap();
+        let base = super::LinkBase {
+            root_url: &root,
+            site_root: "/",
+            base_url: None,
+            file_url: &file,
+        };
+        let page = extract_page(Trickle(html.as_bytes()), Some(&base), false).unwrap();
+        assert_eq!(page.redirect.as_deref(), Some("../other.html"));
+        assert!(page.anchors.contains("caf\u{e9}"));
+        assert_eq!(page.links.len(), 1);
+        assert_eq!(page.links[0].url.path(), "/base/caf%C3%A9.html");
+        assert_eq!(
+            page.links[0].position,
+            Position {
+                offset: 103,
+                line: 2,
+                column: 25
+            }
+        );
+        assert!(html[103..].starts_with("<a href"));
+    }
+
+    #[test]
+    fn test_positions_in_other_encodings() {
+        let html = b"<meta charset=\"windows-1252\">\n\xe9 <a href=\"a.html\">a</a>";
+        let root = Url::from_directory_path("/base").unwrap();
+        let file = Url::from_file_path("/base/test.html").unwrap();
+        let base = super::LinkBase {
+            root_url: &root,
+            site_root: "/",
+            base_url: None,
+            file_url: &file,
+        };
+        let page = extract_page(&html[..], Some(&base), false).unwrap();
+        // `\xe9` is `é`, which is one byte in the file but two in UTF-8.
+        assert_eq!(
+            page.links[0].position,
+            Position {
+                offset: 33,
+                line: 2,
+                column: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_malformed_html() {
+        // A browser ignores either `<select>` or `<xmp>`, so it's unclear whether `<script>` is a tag.
+        let html = r#"<select><xmp><script>"use strict";</script></select><a href="a.html">a</a>"#;
+        let root = Url::from_directory_path("/base").unwrap();
+        let file = Url::from_file_path("/base/test.html").unwrap();
+        assert!(matches!(
+            parse_links(html, &root, "/", None, &file),
+            Err(PageError::Parse(_))
+        ));
+        assert!(matches!(
+            extract_page(html.as_bytes(), None, false),
+            Err(PageError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_redirect() {
+        let html = r#"<html><head>
+            <meta http-equiv="refresh" content="0;URL=../first.html">
+            <meta http-equiv="refresh" content="0;URL=../second.html">
+            </head></html>"#;
+        let redirect = |html: &str| extract_page(html.as_bytes(), None, false).unwrap().redirect;
+        assert_eq!(redirect(html), Some("../first.html".to_owned()));
+        assert_eq!(redirect("<p>no redirect</p>"), None);
+    }
+
+    #[test]
+    fn test_read_html() {
+        assert_eq!(decode(b"<p>caf\xc3\xa9</p>").unwrap(), "<p>caf\u{e9}</p>");
+        assert_eq!(
+            decode(b"<meta charset=\"ISO-8859-1\"><p>caf\xe9</p>").unwrap(),
+            "<meta charset=\"ISO-8859-1\"><p>caf\u{e9}</p>"
+        );
+        assert_eq!(
+            decode(b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=windows-1252\">\x93")
+                .unwrap(),
+            "<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=windows-1252\">\u{201c}"
+        );
+        assert_eq!(decode(b"\xff\xfe<\0p\0>\0").unwrap(), "<p>");
+        assert!(decode(b"<p>caf\xe9</p>").is_err());
+        assert!(decode(b"<p>caf\xc3").is_err());
+        assert!(decode(b"<meta charset=utf-8><p>caf\xe9</p>").is_err());
+        assert!(decode(b"<meta charset=klingon><p>caf\xe9</p>").is_err());
 
-        assert!(fragments.contains("a"));
-        assert!(fragments.contains("h1"));
+        // The encoding is only declared near the start of the file, and the rest is read in chunks.
+        let mut long = b"<meta charset=windows-1252>".to_vec();
+        long.resize(3000, b' ');
+        long.extend_from_slice(b"\x93");
+        assert!(decode(&long).unwrap().ends_with(" \u{201c}"));
+        let mut long = b"<p>".to_vec();
+        long.resize(3000, b' ');
+        long.extend_from_slice("\u{2615}".as_bytes());
+        assert!(decode(&long).unwrap().ends_with(" \u{2615}"));
+        long.push(0xe2);
+        assert!(decode(&long).is_err());
     }
 }
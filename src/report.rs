@@ -0,0 +1,463 @@
+//! Machine-readable reports of all errors found during a run.
+//!
+//! # JSON schema
+//!
+//! The document produced by [`Report::to_json`] looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "directories": [
+//!     {
+//!       "root": "/home/user/my_crate/target/doc/my_crate",
+//!       "files": [
+//!         {
+//!           "path": "fn.foo.html",
+//!           "errors": [
+//!             {
+//!               "position": { "offset": 1042, "line": 12, "column": 5 },
+//!               "severity": "error",
+//!               "origin": {
+//!                 "item": "my_crate::foo",
+//!                 "source": { "path": "src/lib.rs", "line": 7 }
+//!               },
+//!               "kind": "missing-fragment",
+//!               "message": "Fragment #bar at index.html does not exist!",
+//!               "link": "index.html",
+//!               "fragment": "bar"
+//!             }
+//!           ]
+//!         }
+//!       ]
+//!     }
+//!   ],
+//!   "summary": { "directories": 1, "files": 1, "errors": 1, "warnings": 0 }
+//! }
+//! ```
+//!
+//! `path` is relative to `root` unless verbose output was requested.
+//! `position` is where the element containing the link starts in the HTML file, if known:
+//! `offset` is in bytes and starts at 0, while `line` and `column` (in characters) start at 1.
+//! `offset` counts the bytes of the page decoded as UTF-8, so it is only a byte offset into the file itself
+//! for UTF-8 files; for pages in another encoding (e.g. `<meta charset="windows-1252">`), use `line` and `column`.
+//! `severity` is `warning` for permanent redirects and errors which might go away by themselves, such as
+//! timeouts or rate limiting, unless transient errors are denied; otherwise it is `error`. Only errors cause the check to fail.
+//! `origin` is the item whose documentation contains the link, and the file and line of the Rust source
+//! it was written in, if known. Both `item` and `source` may be missing; the line is a best guess,
+//! and is the start of the item if the link could not be found in its doc comment.
+//! Each error always has a `kind` and a human-readable `message`. `kind` is one of:
+//!
+//! - `intra-doc-link`: an intra-doc link was not resolved by rustdoc. `link` is the text of the link.
+//! - `missing-file`: a linked file does not exist. `link` is the path to the file.
+//! - `missing-fragment`: a linked file exists, but not the fragment. `link` and `fragment` are
+//!   always present. For ranged fragments like `#10-20`, `missing_fragments` lists the lines that do not exist.
+//! - `http-error`: a linked URL does not exist. `link` is the URL.
+//! - `http-forbidden`: an HTTP link was found, but HTTP links are forbidden. `link` is the URL.
+//! - `permanent-redirect`: a linked URL redirects with 301 or 308, so the link should be updated.
+//!   This is always a warning. `link` is the URL, and `redirects` lists each redirect as `{ "status", "location" }`.
+//! - `too-many-redirects`: a linked URL redirected more often than allowed. `link` and `redirects` are present.
+//! - `cross-host-redirect`: a linked URL redirects to a different host, and cross-host redirects are denied.
+//!   `link` and `redirects` are present.
+//! - `io`: an error occurred while fetching a file or URL. `link` is present if known;
+//!   `http_status` is present if the server responded with an unexpected status. This is also the kind of
+//!   HTTP links which were not checked because the HTTP client could not be set up.
+//! - `unreadable-file`: the file itself could not be read, e.g. because it is not UTF-8 and does not declare
+//!   its encoding with `<meta charset>`. `link` is the path to the file, and there is no position.
+//! - `parse-error`: the HTML of a page was too malformed to parse. `link` is the page, which is either
+//!   the file itself (without a position) or a linked page whose fragments had to be checked.
+//! - `orphan-page`: the file itself can't be reached by following links from the entry points; only reported with
+//!   `--orphan-pages`. `link` is the path to the file, and there is no position.
+//!
+//! Fields that do not apply to an error are omitted.
+//! New fields and kinds may be added without changing `version`;
+//! removing or changing the meaning of a field will increment it.
+//!
+//! # SARIF
+//!
+//! [`Report::to_sarif`] emits a [SARIF 2.1.0] log with a single run. Each error `kind` above is a rule,
+//! and each error is a result located in the Rust source it was written in if that is known,
+//! or else in the HTML file (and line and column, if known) it was found in. In the first case, the HTML file
+//! is a related location. The item containing the link is a logical location.
+//! The `level` of each result is its `severity`.
+//! The run sets `columnKind` to `unicodeCodePoints`, since columns count characters, not UTF-16 code units.
+//!
+//! [SARIF 2.1.0]: https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::Serialize;
+use serde_json::json;
+use url::Url;
+
+use crate::{FileError, LinkError, Severity};
+
+/// The version of the JSON schema emitted by [`Report::to_json`].
+pub const REPORT_VERSION: u32 = 1;
+
+/// Every kind of error, along with a description used for SARIF rules.
+const RULES: [(&str, &str); 12] = [
+    (
+        "intra-doc-link",
+        "An intra-doc link was not resolved by rustdoc",
+    ),
+    ("missing-file", "A linked file does not exist"),
+    (
+        "missing-fragment",
+        "A linked file exists, but does not contain the linked fragment",
+    ),
+    ("http-error", "A linked URL does not exist"),
+    (
+        "http-forbidden",
+        "An HTTP link was found, but HTTP links are forbidden",
+    ),
+    (
+        "permanent-redirect",
+        "A linked URL permanently redirects to another URL",
+    ),
+    (
+        "too-many-redirects",
+        "A linked URL redirects too many times",
+    ),
+    (
+        "cross-host-redirect",
+        "A linked URL redirects to a different host",
+    ),
+    (
+        "io",
+        "An error occurred while fetching a linked file or URL",
+    ),
+    (
+        "unreadable-file",
+        "A file could not be read, or its encoding could not be determined",
+    ),
+    ("parse-error", "The HTML of a page could not be parsed"),
+    (
+        "orphan-page",
+        "A page can't be reached by following links from the entry points",
+    ),
+];
+
+/// All errors found while checking one or more documentation directories.
+#[derive(Debug, Default)]
+pub struct Report {
+    directories: Vec<DirectoryReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct DirectoryReport {
+    root: PathBuf,
+    files: Vec<FileError>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the errors found in the directory `root`, e.g. by [`crate::check_dir`].
+    pub fn add_dir(&mut self, root: &Path, files: Vec<FileError>) {
+        self.directories.push(DirectoryReport {
+            root: root.to_path_buf(),
+            files,
+        });
+    }
+
+    /// Whether any errors were found in any directory, not counting warnings.
+    pub fn has_errors(&self) -> bool {
+        self.files().any(|(_, file)| file.has_errors())
+    }
+
+    /// Iterate over all files with errors, along with the directory they were found in.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &FileError)> {
+        self.directories
+            .iter()
+            .flat_map(|dir| dir.files.iter().map(move |file| (dir.root.as_path(), file)))
+    }
+
+    /// Iterate over all errors, along with the directory and file they were found in.
+    pub fn errors(&self) -> impl Iterator<Item = (&Path, &FileError, &LinkError)> {
+        self.files()
+            .flat_map(|(root, file)| file.errors.iter().map(move |e| (root, file, e)))
+    }
+
+    /// Only keep the errors for which `keep` returns true, given the directory and file they were found in.
+    ///
+    /// Files without any remaining errors are removed from the report.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path, &Path, &LinkError) -> bool) {
+        for dir in &mut self.directories {
+            let root = &dir.root;
+            for file in &mut dir.files {
+                let path = &file.path;
+                file.errors.retain(|e| keep(root, path, e));
+            }
+            dir.files.retain(|file| !file.errors.is_empty());
+        }
+    }
+
+    /// Render the report as a JSON document, following the schema described in the module documentation.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Summary {
+            directories: usize,
+            files: usize,
+            errors: usize,
+            warnings: usize,
+        }
+
+        #[derive(Serialize)]
+        struct JsonReport<'a> {
+            version: u32,
+            directories: &'a [DirectoryReport],
+            summary: Summary,
+        }
+
+        let report = JsonReport {
+            version: REPORT_VERSION,
+            directories: &self.directories,
+            summary: Summary {
+                directories: self.directories.len(),
+                files: self.files().count(),
+                errors: self
+                    .errors()
+                    .filter(|(_, _, e)| e.severity == Severity::Error)
+                    .count(),
+                warnings: self
+                    .errors()
+                    .filter(|(_, _, e)| e.severity == Severity::Warning)
+                    .count(),
+            },
+        };
+        serde_json::to_string_pretty(&report).expect("reports are always valid JSON")
+    }
+
+    /// Render the report as a SARIF 2.1.0 log.
+    pub fn to_sarif(&self) -> String {
+        let rules: Vec<_> = RULES
+            .iter()
+            .map(|(id, description)| {
+                json!({
+                    "id": id,
+                    "shortDescription": { "text": description },
+                    "defaultConfiguration": { "level": "error" },
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = self
+            .files()
+            .flat_map(|(root, file)| {
+                let path = root.join(&file.path);
+                let uri = Url::from_file_path(&path)
+                    .map(String::from)
+                    .unwrap_or_else(|()| path.display().to_string());
+                file.errors.iter().map(move |e| {
+                    let rule_index = RULES
+                        .iter()
+                        .position(|(id, _)| *id == e.error.kind())
+                        .expect("every kind of error should have a rule");
+                    let mut location = json!({
+                        "artifactLocation": { "uri": uri },
+                    });
+                    if let Some(pos) = e.position {
+                        location["region"] = json!({
+                            "startLine": pos.line,
+                            "startColumn": pos.column,
+                        });
+                    }
+                    let mut result = json!({
+                        "ruleId": e.error.kind(),
+                        "ruleIndex": rule_index,
+                        "level": e.severity,
+                        "message": { "text": e.error.to_string() },
+                        "locations": [{ "physicalLocation": location }],
+                    });
+                    let origin = match &e.origin {
+                        Some(origin) => origin,
+                        None => return result,
+                    };
+                    // Prefer the Rust source, since that's what has to be changed to fix the link.
+                    if let Some(source) = &origin.source {
+                        result["relatedLocations"] =
+                            json!([{ "id": 0, "physicalLocation": location }]);
+                        result["locations"][0]["physicalLocation"] = json!({
+                            "artifactLocation": { "uri": source_uri(&source.path) },
+                            "region": { "startLine": source.line },
+                        });
+                    }
+                    if let Some(item) = &origin.item {
+                        result["locations"][0]["logicalLocations"] =
+                            json!([{ "fullyQualifiedName": item }]);
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        let log = json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "cargo-deadlinks",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                        "rules": rules,
+                    },
+                },
+                "columnKind": "unicodeCodePoints",
+                "results": results,
+            }],
+        });
+        serde_json::to_string_pretty(&log).expect("reports are always valid JSON")
+    }
+}
+
+/// The SARIF URI of a Rust source file. Relative paths are kept relative, since they are relative to
+/// where the check was run, which is usually also where the results are viewed.
+fn source_uri(path: &Path) -> String {
+    if path.is_absolute() {
+        if let Ok(url) = Url::from_file_path(path) {
+            return url.into();
+        }
+    }
+    let components: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::Report;
+    use crate::{
+        CheckError, FileError, Link, LinkError, Origin, Position, Severity, SourceLocation,
+    };
+
+    #[test]
+    fn test_json_report() {
+        let mut report = Report::new();
+        report.add_dir(Path::new("/empty"), Vec::new());
+        assert!(!report.has_errors());
+
+        report.add_dir(
+            Path::new("/doc"),
+            vec![FileError {
+                path: PathBuf::from("fn.foo.html"),
+                errors: vec![
+                    LinkError {
+                        position: Some(Position {
+                            offset: 20,
+                            line: 2,
+                            column: 5,
+                        }),
+                        severity: Severity::Error,
+                        origin: None,
+                        error: CheckError::File(PathBuf::from("fn.bar.html")),
+                    },
+                    CheckError::Fragment(
+                        Link::File(PathBuf::from("src/lib.rs.html")),
+                        "4-6".into(),
+                        Some(vec!["6".into()]),
+                    )
+                    .into(),
+                ],
+            }],
+        );
+        assert!(report.has_errors());
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "directories": [
+                    { "root": "/empty", "files": [] },
+                    {
+                        "root": "/doc",
+                        "files": [{
+                            "path": "fn.foo.html",
+                            "errors": [
+                                {
+                                    "position": { "offset": 20, "line": 2, "column": 5 },
+                                    "severity": "error",
+                                    "kind": "missing-file",
+                                    "message": "Linked file at path fn.bar.html does not exist!",
+                                    "link": "fn.bar.html",
+                                },
+                                {
+                                    "severity": "error",
+                                    "kind": "missing-fragment",
+                                    "message": "Fragments #6 as expected by ranged fragment #4-6 at src/lib.rs.html do not exist!\nThis is likely a bug in rustdoc itself.",
+                                    "link": "src/lib.rs.html",
+                                    "fragment": "4-6",
+                                    "missing_fragments": ["6"],
+                                },
+                            ],
+                        }],
+                    },
+                ],
+                "summary": { "directories": 2, "files": 1, "errors": 2, "warnings": 0 },
+            })
+        );
+    }
+
+    #[test]
+    fn test_sarif_report() {
+        let mut report = Report::new();
+        report.add_dir(
+            Path::new("/doc"),
+            vec![FileError {
+                path: PathBuf::from("fn.foo.html"),
+                errors: vec![LinkError {
+                    position: Some(Position {
+                        offset: 100,
+                        line: 7,
+                        column: 3,
+                    }),
+                    severity: Severity::Warning,
+                    origin: Some(Origin {
+                        item: Some("krate::foo".into()),
+                        source: Some(SourceLocation {
+                            path: PathBuf::from("src/lib.rs"),
+                            line: 3,
+                        }),
+                    }),
+                    error: CheckError::IntraDocLink("[<code>bar</code>]".into()),
+                }],
+            }],
+        );
+
+        let sarif: serde_json::Value = serde_json::from_str(&report.to_sarif()).unwrap();
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        assert_eq!(run["columnKind"], "unicodeCodePoints");
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 12);
+        assert_eq!(
+            run["results"],
+            serde_json::json!([{
+                "ruleId": "intra-doc-link",
+                "ruleIndex": 0,
+                "level": "warning",
+                "message": { "text": "Broken intra-doc link to [<code>bar</code>]!" },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "src/lib.rs" },
+                        "region": { "startLine": 3 },
+                    },
+                    "logicalLocations": [{ "fullyQualifiedName": "krate::foo" }],
+                }],
+                "relatedLocations": [{
+                    "id": 0,
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "file:///doc/fn.foo.html" },
+                        "region": { "startLine": 7, "startColumn": 3 },
+                    },
+                }],
+            }])
+        );
+    }
+}
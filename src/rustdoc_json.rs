@@ -0,0 +1,395 @@
+//! Checking the doc comments in rustdoc's JSON output (`-Z unstable-options --output-format json`).
+//!
+//! Unlike the HTML output, rustdoc JSON records which intra-doc links were resolved for each item,
+//! so broken intra-doc links are found without guessing from the rendered HTML.
+//! Only the fields needed here are parsed, so that this works with as many versions of the format as possible.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use log::info;
+use pulldown_cmark::{BrokenLink, Event, Options, Parser, Tag};
+use serde_derive::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::check::is_available_offline;
+use crate::origin::find_in_doc_comments;
+use crate::{
+    http, is_ignored_link, CheckContext, CheckError, FileError, LinkError, Origin, SiteIndex,
+    SourceLocation,
+};
+
+/// Keys in the `inner` object of an item which list the ids of its members.
+const MEMBER_KEYS: [&str; 4] = ["fields", "impls", "items", "variants"];
+
+#[derive(Deserialize)]
+struct Crate {
+    index: HashMap<String, Item>,
+    paths: HashMap<String, ItemSummary>,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    /// `0` for items of the documented crate.
+    crate_id: u32,
+    name: Option<String>,
+    span: Option<Span>,
+    docs: Option<String>,
+    /// The target of each intra-doc link that rustdoc resolved, by the link as written in `docs`.
+    #[serde(default)]
+    links: HashMap<String, Value>,
+    #[serde(default)]
+    inner: Value,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    /// Relative to the directory rustdoc was run in.
+    filename: PathBuf,
+    /// `(line, column)`, both starting at 1.
+    begin: (usize, usize),
+    end: (usize, usize),
+}
+
+#[derive(Deserialize)]
+struct ItemSummary {
+    crate_id: u32,
+    path: Vec<String>,
+}
+
+/// Item ids are strings in older versions of the format, and integers in newer ones.
+fn id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Collect the ids of the members of an item (fields, variants, impls, and the items of traits and impls).
+fn members(inner: &Value, ids: &mut Vec<String>) {
+    match inner {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    Value::Array(values) if MEMBER_KEYS.contains(&key.as_str()) => {
+                        ids.extend(values.iter().filter_map(id))
+                    }
+                    _ => members(value, ids),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| members(value, ids)),
+        _ => {}
+    }
+}
+
+/// The full path of every item of the documented crate which can be named, e.g. `my_crate::Foo::bar` for a method.
+fn item_paths(krate: &Crate) -> HashMap<&str, String> {
+    let mut paths: HashMap<&str, String> = krate
+        .paths
+        .iter()
+        .filter(|(_, summary)| summary.crate_id == 0)
+        .map(|(id, summary)| (id.as_str(), summary.path.join("::")))
+        .collect();
+    let mut stack: Vec<_> = paths.iter().map(|(&id, path)| (id, path.clone())).collect();
+    while let Some((id, path)) = stack.pop() {
+        let item = match krate.index.get(id) {
+            Some(item) => item,
+            None => continue,
+        };
+        let mut ids = Vec::new();
+        members(&item.inner, &mut ids);
+        for member in ids {
+            let (member, item) = match krate.index.get_key_value(&member) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if paths.contains_key(member.as_str()) {
+                continue;
+            }
+            // Impls don't have a name, so their items belong to the type they are implemented for.
+            let member_path = match &item.name {
+                Some(name) => format!("{}::{}", path, name),
+                None => path.clone(),
+            };
+            paths.insert(member, member_path.clone());
+            stack.push((member, member_path));
+        }
+    }
+    paths
+}
+
+/// The destination of every link and image in `docs`, along with its offset.
+fn doc_links(docs: &str) -> Vec<(String, usize)> {
+    // Like rustdoc, treat references without a definition (e.g. ``[`Foo`]``) as links to their text.
+    let mut resolve = |link: BrokenLink<'_>| Some((link.reference.to_string().into(), "".into()));
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_STRIKETHROUGH;
+    Parser::new_with_broken_link_callback(docs, options, Some(&mut resolve))
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::Link(_, dest, _)) | Event::Start(Tag::Image(_, dest, _)) => {
+                Some((dest.into_string(), range.start))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether rustdoc tries to resolve `dest` as an intra-doc link, rather than treating it as a URL.
+///
+/// This follows rustdoc's own heuristic: anything with a `/` is a URL or relative path,
+/// and anything else is a path, possibly with a disambiguator, generics or a fragment.
+fn is_intra_doc_link(dest: &str) -> bool {
+    if dest.contains('/') {
+        return false;
+    }
+    let dest = dest.replace('`', "");
+    let path = dest.split('#').next().unwrap().trim();
+    !path.is_empty()
+        && path
+            .chars()
+            .all(|c| c.is_alphanumeric() || ":_<>, !*&;@()".contains(c))
+}
+
+/// Check the doc comments of every item in a rustdoc JSON file.
+///
+/// Intra-doc links which rustdoc did not resolve are always reported, even without
+/// [`CheckContext::check_intra_doc_links`]. URLs are checked like links in HTML files;
+/// relative links depend on where rustdoc puts the HTML pages, so they can only be checked there.
+///
+/// `source_root` is the directory rustdoc was run in (for cargo, the root of the workspace).
+/// Each error has an [`Origin`] in the Rust source, relative to `source_root`.
+/// The errors are returned as a single [`FileError`] for the JSON file, if any were found.
+/// Unless `ctx.verbose` is set, its path is relative to the directory containing the JSON file.
+pub fn check_rustdoc_json(
+    path: &Path,
+    source_root: &Path,
+    ctx: &CheckContext,
+) -> io::Result<Vec<FileError>> {
+    info!("Checking rustdoc JSON at {}", path.display());
+    let krate: Crate = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let root = path.parent().unwrap_or_else(|| Path::new(""));
+    let paths = item_paths(&krate);
+
+    let mut items: Vec<_> = krate
+        .index
+        .iter()
+        .filter(|(_, item)| item.crate_id == 0)
+        .collect();
+    items.sort_by_key(|(_, item)| item.span.as_ref().map(|span| (&span.filename, span.begin)));
+
+    let mut sources = HashMap::new();
+    let mut origin = |id: &str, item: &Item, needle: &str| {
+        let source = item.span.as_ref().map(|span| {
+            let path = source_root.join(&span.filename);
+            let lines = sources.entry(path.clone()).or_insert_with(|| {
+                std::fs::read_to_string(&path)
+                    .ok()
+                    .map(|source| source.lines().map(str::to_owned).collect::<Vec<_>>())
+            });
+            let line = lines
+                .as_ref()
+                .and_then(|lines| find_in_doc_comments(lines, span.begin.0, span.end.0, needle));
+            SourceLocation {
+                path,
+                line: line.unwrap_or(span.begin.0),
+            }
+        });
+        Origin {
+            item: paths.get(id).cloned().or_else(|| item.name.clone()),
+            source,
+        }
+    };
+
+    let mut errors = Vec::new();
+    // HTTP links which have to be fetched, along with where they were found
+    let mut pending = Vec::new();
+    // Doc comments rarely link to local files, so they are only read once they are linked to.
+    let index = SiteIndex::default();
+    for (id, item) in items {
+        let docs = match &item.docs {
+            Some(docs) => docs,
+            None => continue,
+        };
+        for (dest, _) in doc_links(docs) {
+            let error = if is_intra_doc_link(&dest) {
+                match item.links.get(&dest).and_then(self::id) {
+                    Some(target)
+                        if krate.index.contains_key(&target)
+                            || krate.paths.contains_key(&target) =>
+                    {
+                        continue
+                    }
+                    _ => CheckError::IntraDocLink(format!("[{}]", dest)),
+                }
+            } else if let Ok(url) = Url::parse(&dest) {
+                if is_ignored_link(&url, root, ctx) {
+                    continue;
+                }
+                match is_available_offline(&url, ctx, &index) {
+                    Some(Ok(())) => continue,
+                    Some(Err(error)) => error,
+                    None => {
+                        pending.push((url, origin(id, item, &dest)));
+                        continue;
+                    }
+                }
+            } else {
+                continue;
+            };
+            let mut error = LinkError::new(None, error, ctx);
+            error.origin = Some(origin(id, item, &dest));
+            errors.push(error);
+        }
+    }
+
+    let http_results = http::check_urls(pending.iter().map(|(url, _)| url.clone()), ctx);
+    errors.extend(pending.into_iter().filter_map(|(url, origin)| {
+        let mut error = LinkError::new(None, http_results[&url].clone().err()?, ctx);
+        error.origin = Some(origin);
+        Some(error)
+    }));
+    errors.sort_by(|a, b| {
+        let location = |e: &LinkError| {
+            e.origin
+                .as_ref()
+                .and_then(|origin| origin.source.as_ref())
+                .map(|source| (source.path.clone(), source.line))
+        };
+        location(a).cmp(&location(b))
+    });
+
+    if errors.is_empty() {
+        return Ok(Vec::new());
+    }
+    let path = if ctx.verbose {
+        path.to_owned()
+    } else {
+        path.strip_prefix(root).unwrap_or(path).to_owned()
+    };
+    Ok(vec![FileError { path, errors }])
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{check_rustdoc_json, doc_links, is_intra_doc_link};
+    use crate::CheckContext;
+
+    #[test]
+    fn test_doc_links() {
+        let docs =
+            "See [`Foo`], [bar](crate::bar), <https://example.com>, [x][y] and ![img](a.png).\n\n\
+                    ```\nlet x = [not_a_link];\n```\n\n[y]: https://example.com/y";
+        let dests: Vec<_> = doc_links(docs).into_iter().map(|(dest, _)| dest).collect();
+        assert_eq!(
+            dests,
+            [
+                "`Foo`",
+                "crate::bar",
+                "https://example.com",
+                "https://example.com/y",
+                "a.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_intra_doc_link() {
+        assert!(is_intra_doc_link("`Foo`"));
+        assert!(is_intra_doc_link("crate::foo::Bar"));
+        assert!(is_intra_doc_link("fn@foo"));
+        assert!(is_intra_doc_link("Vec<T>::push()"));
+        assert!(is_intra_doc_link("Foo#method.bar"));
+        assert!(!is_intra_doc_link("#fragment"));
+        assert!(!is_intra_doc_link("fn.foo.html"));
+        assert!(!is_intra_doc_link("https://example.com"));
+        assert!(!is_intra_doc_link("../index.html"));
+    }
+
+    #[test]
+    fn test_check_rustdoc_json() {
+        let dir =
+            std::env::temp_dir().join(format!("deadlinks-rustdoc-json-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(
+            dir.join("src/lib.rs"),
+            "/// Docs\npub struct Foo;\n\nimpl Foo {\n    /// See [`Foo`], [`Bar`] and [gone](Foo::gone).\n    pub fn bar() {}\n}\n",
+        )
+        .unwrap();
+        // The format of newer versions of rustdoc, trimmed down to what is needed.
+        let json = serde_json::json!({
+            "root": 0,
+            "format_version": 57,
+            "index": {
+                "0": { "crate_id": 0, "name": "krate", "docs": null, "links": {}, "inner": { "module": { "items": [1] } } },
+                "1": {
+                    "crate_id": 0, "name": "Foo", "docs": "Docs", "links": {},
+                    "span": { "filename": "src/lib.rs", "begin": [2, 1], "end": [2, 16] },
+                    "inner": { "struct": { "kind": "unit", "impls": [2] } },
+                },
+                "2": {
+                    "crate_id": 0, "name": null, "docs": null, "links": {},
+                    "span": { "filename": "src/lib.rs", "begin": [4, 1], "end": [7, 2] },
+                    "inner": { "impl": { "items": [3], "trait": null } },
+                },
+                "3": {
+                    "crate_id": 0, "name": "bar",
+                    "docs": "See [`Foo`], [`Bar`] and [gone](Foo::gone).",
+                    "links": { "`Foo`": 1, "Foo::gone": 42 },
+                    "span": { "filename": "src/lib.rs", "begin": [6, 5], "end": [6, 20] },
+                    "inner": { "function": {} },
+                },
+            },
+            "paths": {
+                "0": { "crate_id": 0, "path": ["krate"], "kind": "module" },
+                "1": { "crate_id": 0, "path": ["krate", "Foo"], "kind": "struct" },
+            },
+        });
+        let json_path = dir.join("krate.json");
+        std::fs::write(&json_path, json.to_string()).unwrap();
+
+        let files = check_rustdoc_json(&json_path, &dir, &CheckContext::default()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, Path::new("krate.json"));
+        let errors: Vec<_> = files[0]
+            .errors
+            .iter()
+            .map(|e| {
+                let origin = e.origin.as_ref().unwrap();
+                let source = origin.source.as_ref().unwrap();
+                (
+                    e.error.to_string(),
+                    origin.item.as_deref().unwrap(),
+                    source.path.strip_prefix(&dir).unwrap(),
+                    source.line,
+                )
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "Broken intra-doc link to [`Bar`]!".to_owned(),
+                    "krate::Foo::bar",
+                    Path::new("src/lib.rs"),
+                    5
+                ),
+                (
+                    "Broken intra-doc link to [Foo::gone]!".to_owned(),
+                    "krate::Foo::bar",
+                    Path::new("src/lib.rs"),
+                    5
+                ),
+            ]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn checks_built_book() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--book", ".", "--no-build"])
+        .current_dir("./tests/book")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Found invalid urls in usage.html:")
+                .and(contains(
+                    "usage.html:18:76: Linked file at path configuration.html does not exist!",
+                ))
+                // Links under `site-url` are resolved locally, and other absolute links are skipped.
+                .and(contains("my-book").not())
+                .and(contains("other-book").not())
+                // The same error is not reported again for the print page.
+                .and(contains("print.html").not()),
+        );
+}
+
+#[test]
+fn renders_as_mdbook_backend() {
+    let root = std::env::current_dir().unwrap().join("tests/book");
+    let context = serde_json::json!({
+        "version": "0.4.48",
+        "root": root,
+        "book": { "sections": [] },
+        "config": {
+            "book": { "title": "Example book" },
+            "output": { "html": { "site-url": "/my-book/" }, "deadlinks": {} },
+        },
+        "destination": root.join("book/deadlinks"),
+    });
+    // `assert_cmd::Command` can write to standard input, like mdBook does.
+    assert_cmd::Command::cargo_bin("mdbook-deadlinks")
+        .unwrap()
+        .current_dir("./tests/book")
+        .write_stdin(context.to_string())
+        .assert()
+        .failure()
+        .stdout(
+            contains("Found invalid urls in usage.md:")
+                .and(contains(
+                    "usage.md:5:52: Linked file at path configuration.md does not exist!",
+                ))
+                .and(contains("intro.md").not()),
+        );
+}
@@ -2,7 +2,7 @@ extern crate assert_cmd;
 
 use assert_cmd::prelude::*;
 use predicates::prelude::PredicateBooleanExt;
-use predicates::str::contains;
+use predicates::str::{contains, is_match};
 use std::process::Command;
 
 #[test]
@@ -27,6 +27,20 @@ fn reports_broken_links() {
         );
 }
 
+#[test]
+fn reports_positions_of_broken_links() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .arg("deadlinks")
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stdout(
+            is_match(r"\n\tindex\.html:\d+:\d+: Linked file at path links does not exist!")
+                .unwrap(),
+        );
+}
+
 #[test]
 fn does_not_check_intra_doc_by_default() {
     Command::cargo_bin("cargo-deadlinks")
@@ -41,3 +55,253 @@ fn does_not_check_intra_doc_by_default() {
                 .and(contains("Broken intra-doc links").not()),
         );
 }
+
+#[test]
+fn reports_broken_links_as_json() {
+    let output = Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--check-intra-doc-links", "--format", "json"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+
+    let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(report["version"], 1);
+    assert_eq!(report["summary"]["files"], 1);
+
+    let file = &report["directories"][0]["files"][0];
+    assert_eq!(file["path"], "index.html");
+    let errors = file["errors"].as_array().unwrap();
+    assert!(errors
+        .iter()
+        .any(|e| e["kind"] == "missing-file" && e["link"] == "fn.not_here.html"));
+    assert!(errors
+        .iter()
+        .any(|e| e["kind"] == "intra-doc-link" && e["link"] == "[<code>links</code>]"));
+    assert!(errors
+        .iter()
+        .any(|e| e["kind"] == "missing-fragment" && e["fragment"] == "fragments"));
+}
+
+#[test]
+fn reports_broken_links_as_sarif() {
+    let output = Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--format", "sarif"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+
+    let log: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let results = log["runs"][0]["results"].as_array().unwrap();
+    assert!(results.iter().any(|r| r["ruleId"] == "missing-file"));
+    assert!(results.iter().all(|r| {
+        r["locations"][0]["physicalLocation"]["artifactLocation"]["uri"] == "src/lib.rs"
+            && r["relatedLocations"][0]["physicalLocation"]["artifactLocation"]["uri"]
+                .as_str()
+                .unwrap()
+                .ends_with("/broken_links/index.html")
+    }));
+}
+
+#[test]
+fn reports_rust_source_of_broken_links() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--check-intra-doc-links"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stdout(
+            is_match(r"Linked file at path links does not exist!\n\t  --> src/lib\.rs:2 \(broken_links\)")
+                .unwrap()
+                .and(
+                    is_match(r"Broken intra-doc link to \[<code>links</code>\]!\n\t  --> src/lib\.rs:3 ")
+                        .unwrap(),
+                )
+                .and(
+                    is_match(r"Fragment #fragments at index\.html does not exist!\n\t  --> src/lib\.rs:4 ")
+                        .unwrap(),
+                ),
+        );
+
+    let output = Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--format", "json"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let errors = report["directories"][0]["files"][0]["errors"]
+        .as_array()
+        .unwrap();
+    let missing = errors
+        .iter()
+        .find(|e| e["link"] == "fn.not_here.html")
+        .unwrap();
+    assert_eq!(
+        missing["origin"],
+        serde_json::json!({
+            "item": "broken_links",
+            "source": { "path": "src/lib.rs", "line": 1 },
+        })
+    );
+}
+
+#[test]
+fn reads_settings_from_config_file() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--config", "deadlinks-intra-doc.toml"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stdout(contains("Broken intra-doc link to [<code>links</code>]!"));
+
+    // Only `<a>` links are broken, so ignoring them should succeed.
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--config", "deadlinks-media.toml", "hardcoded-target"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .success();
+}
+
+#[test]
+fn command_line_overrides_config_file() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args([
+            "--config",
+            "deadlinks-media.toml",
+            "--link-kinds",
+            "hyperlink",
+        ])
+        .arg("hardcoded-target")
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stdout(contains("Linked file at path x.html does not exist"));
+}
+
+#[test]
+fn rejects_invalid_config_file() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--config", "Cargo.toml", "hardcoded-target"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stderr(contains("error: invalid configuration in Cargo.toml"));
+}
+
+#[test]
+fn rejects_invalid_http_client_settings() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args([
+            "--check-http",
+            "--http-ca-bundle",
+            "missing.pem",
+            "hardcoded-target",
+        ])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stderr(contains("error: could not read CA bundle missing.pem"));
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args([
+            "--http-header",
+            "** Authorization: ${DEADLINKS_UNSET_TOKEN}",
+        ])
+        .arg("hardcoded-target")
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stdout(contains(
+            "environment variable DEADLINKS_UNSET_TOKEN is not set",
+        ));
+}
+
+#[test]
+fn ignores_links_and_files() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--ignore-link", "fn.not_here.html"])
+        .args(["--ignore-link", "regex:^links$", "--ignore-link", "*.html"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .success()
+        .stderr(
+            contains("note: ignored 1 links matching 'fn.not_here.html'")
+                .and(contains("note: ignored 1 links matching 'regex:^links$'"))
+                // the exact number depends on the version of rustdoc
+                .and(is_match(r"note: ignored \d+ links matching '\*\.html'").unwrap()),
+        );
+
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--ignore-file", "index.html"])
+        .current_dir("./tests/broken_links")
+        .assert()
+        .success()
+        .stderr(contains(
+            "note: skipped 1 files or directories matching 'index.html'",
+        ));
+}
+
+#[test]
+fn baseline_suppresses_known_errors() {
+    let dir = std::env::temp_dir().join(format!("deadlinks-baseline-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let baseline = dir.join("baseline.json");
+
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .arg("deadlinks")
+        .arg("--write-baseline")
+        .arg(&baseline)
+        .current_dir("./tests/broken_links")
+        .assert()
+        .success()
+        .stderr(contains("note: wrote 4 known errors to"));
+
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .arg("deadlinks")
+        .arg("--baseline")
+        .arg(&baseline)
+        .current_dir("./tests/broken_links")
+        .assert()
+        .success()
+        .stdout("");
+
+    // New errors still fail, and errors which no longer occur are reported.
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--check-intra-doc-links"])
+        .args(["--ignore-link", "fn.not_here.html", "--baseline"])
+        .arg(&baseline)
+        .current_dir("./tests/broken_links")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Broken intra-doc link to [<code>links</code>]!")
+                .and(contains("Linked file at path links does not exist").not()),
+        )
+        .stderr(contains("note: 1 errors in the baseline").and(contains(
+            "broken_links/index.html: missing-file fn.not_here.html",
+        )));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
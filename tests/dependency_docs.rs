@@ -0,0 +1,40 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn checks_links_into_dependency_docs() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--with-deps"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir("./tests/dependency_docs")
+        .assert()
+        .failure()
+        .stdout(
+            contains("helper/struct.Gone.html does not exist!")
+                .and(contains("Fragment #method.gone at"))
+                // Only the workspace's own crates are checked.
+                .and(contains("nowhere.html").not()),
+        );
+}
+
+#[test]
+fn checks_docs_rs_links_against_dependency_docs() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--with-deps", "--local-docs"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir("./tests/dependency_docs")
+        .assert()
+        .failure()
+        .stdout(
+            contains(
+                "Linked URL https://docs.rs/helper/0.1.0/helper/struct.Missing.html does not exist!",
+            )
+            .and(contains("https://docs.rs/helper/0.1.0/helper/struct.Helper.html").not()),
+        );
+}
@@ -0,0 +1,4 @@
+//! Errors in dependencies, like [this one](nowhere.html), are not reported.
+
+/// A dependency's type.
+pub struct Helper;
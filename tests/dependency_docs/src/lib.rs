@@ -0,0 +1,5 @@
+//! Links into the documentation of a dependency: [`helper::Helper`] exists,
+//! but [this struct](../helper/struct.Gone.html) and [this method](../helper/struct.Helper.html#method.gone) don't.
+//!
+//! The same links on docs.rs: [`Helper`](https://docs.rs/helper/0.1.0/helper/struct.Helper.html) exists,
+//! but [`Missing`](https://docs.rs/helper/0.1.0/helper/struct.Missing.html) doesn't.
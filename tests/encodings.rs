@@ -0,0 +1,33 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn reports_unreadable_files_and_decodes_declared_encodings() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .arg("tests/encodings")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Could not read undeclared.html: the file is not valid UTF-8")
+                // `latin1.html` declares its encoding, so it can be checked.
+                .and(contains(
+                    "latin1.html:3:18: Linked file at path missing.html does not exist!",
+                ))
+                .and(contains("#caf").not()),
+        );
+}
+
+#[test]
+fn reports_unreadable_files_in_json() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--format", "json", "tests/encodings"])
+        .assert()
+        .failure()
+        .stdout(contains(r#""kind": "unreadable-file""#));
+}
@@ -0,0 +1,22 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn reports_malformed_pages_and_keeps_checking() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .arg("tests/malformed_html")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Could not parse ambiguous.html: ")
+                .and(contains("index.html:4:1: Could not parse ambiguous.html: "))
+                .and(contains(
+                    "index.html:5:1: Linked file at path missing.html does not exist!",
+                )),
+        );
+}
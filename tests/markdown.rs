@@ -0,0 +1,52 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn reports_broken_links_in_markdown() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--markdown"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir("./tests/markdown")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Found invalid urls in README.md:")
+                .and(contains(
+                    "README.md:5:9: Linked file at path docs/missing.md does not exist!",
+                ))
+                .and(contains(
+                    "README.md:5:45: Fragment #installation at docs/guide.md does not exist!",
+                ))
+                .and(contains(
+                    "README.md:9:41: Fragment #nowhere at README.md does not exist!",
+                ))
+                .and(contains(
+                    "docs/guide.md:7:1: Linked file at path docs/logo.png does not exist!",
+                ))
+                // Headings, reference links and directories are all found.
+                .and(contains("#usage-with-cargo").not())
+                .and(contains("#contributing").not())
+                .and(contains("#markdown").not())
+                .and(contains("docs/ ").not()),
+        );
+}
+
+#[test]
+fn checks_single_markdown_file() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .arg("docs/guide.md")
+        .current_dir("./tests/markdown")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Found invalid urls in guide.md:")
+                .and(contains("Linked file at path logo.png does not exist!"))
+                .and(contains("README.md").not()),
+        );
+}
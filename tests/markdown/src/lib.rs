@@ -0,0 +1 @@
+//! A crate whose README is checked with `--markdown`.
@@ -0,0 +1,45 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn suggests_fix_for_moved_item() {
+    let source = std::fs::read_to_string("./tests/moved_item/src/lib.rs").unwrap();
+
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--fix", "--dry-run"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir("./tests/moved_item")
+        .assert()
+        .failure()
+        .stdout(contains(
+            "Linked file at path fn.moved.html does not exist!",
+        ))
+        .stderr(
+            contains("-//! The [moved function](fn.moved.html) now lives in a module.")
+                .and(contains(
+                    "+//! The [moved function](module/fn.moved.html) now lives in a module.",
+                ))
+                .and(contains("note: would change 1 line\n")),
+        );
+
+    // `--dry-run` doesn't change anything
+    assert_eq!(
+        std::fs::read_to_string("./tests/moved_item/src/lib.rs").unwrap(),
+        source
+    );
+}
+
+#[test]
+fn dry_run_requires_fix() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--dry-run", "tests/html"])
+        .assert()
+        .failure()
+        .stdout(contains("--dry-run can only be used with --fix"));
+}
@@ -0,0 +1,6 @@
+//! The [moved function](fn.moved.html) now lives in a module.
+
+pub mod module {
+    /// Moved from the crate root.
+    pub fn moved() {}
+}
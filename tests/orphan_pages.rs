@@ -0,0 +1,96 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn orphan_pages_are_not_reported_by_default() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .arg("tests/orphan_pages")
+        .assert()
+        .success();
+}
+
+#[test]
+fn reports_pages_which_cant_be_reached() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--orphan-pages", "tests/orphan_pages"])
+        .assert()
+        .failure()
+        .stdout(
+            contains("Page orphan.html can't be reached")
+                .and(contains("Page stale.html can't be reached"))
+                .and(contains("Page 404.html can't be reached"))
+                .and(contains("Page redirect.html").not())
+                .and(contains("Page linked.html").not())
+                .and(contains("Page guide").not()),
+        );
+}
+
+#[test]
+fn follows_links_from_extra_entry_points() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args([
+            "--orphan-pages",
+            "--entry-point",
+            "404.html",
+            "--entry-point",
+            "redirect.html",
+            "tests/orphan_pages",
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn follows_links_through_ignored_pages() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args([
+            "--orphan-pages",
+            "--ignore-file",
+            "guide/index.html",
+            "tests/orphan_pages",
+        ])
+        .assert()
+        .failure()
+        .stdout(contains("Page orphan.html can't be reached").and(contains("Page guide").not()));
+}
+
+#[test]
+fn command_line_overrides_config_file() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--config", "tests/orphan_pages.toml", "tests/orphan_pages"])
+        .assert()
+        .failure()
+        .stdout(contains("Page orphan.html can't be reached"));
+
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args([
+            "--config",
+            "tests/orphan_pages.toml",
+            "--no-orphan-pages",
+            "tests/orphan_pages",
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn rejects_contradicting_flags() {
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .args(["--orphan-pages", "--no-orphan-pages", "tests/orphan_pages"])
+        .assert()
+        .failure()
+        .stdout(contains(
+            "--orphan-pages and --no-orphan-pages are mutually incompatible",
+        ));
+}
@@ -0,0 +1,42 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn reports_broken_intra_doc_links_from_rustdoc_json() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--rustdoc-json"])
+        .env_remove("CARGO_TARGET_DIR")
+        // rustdoc JSON is unstable; this allows using it on a stable toolchain in tests.
+        .env("RUSTC_BOOTSTRAP", "1")
+        .current_dir("./tests/rustdoc_json")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Found invalid urls in rustdoc_json.json:")
+                .and(contains(
+                    "Broken intra-doc link to [`Missing`]!\n\t  --> src/lib.rs:1 (rustdoc_json)\n",
+                ))
+                .and(contains(
+                    "Broken intra-doc link to [Documented::missing]!\n\t  --> src/lib.rs:3 (rustdoc_json::Documented)\n",
+                ))
+                .and(contains(
+                    "Broken intra-doc link to [`crate::nowhere`]!\n\t  --> src/lib.rs:7 (rustdoc_json::Documented::method)\n",
+                ))
+                .and(contains("Documented::method]").not())
+                .and(contains("[`Documented`]").not()),
+        );
+
+    // JSON files can also be checked directly.
+    Command::cargo_bin("deadlinks")
+        .unwrap()
+        .arg("target/doc/rustdoc_json.json")
+        .current_dir("./tests/rustdoc_json")
+        .assert()
+        .failure()
+        .stdout(contains("Broken intra-doc link to [`Missing`]!"));
+}
@@ -0,0 +1,9 @@
+//! Links to [`Documented`] and [`Missing`].
+
+/// A [working link](Documented::method) and a [broken one](Documented::missing).
+pub struct Documented;
+
+impl Documented {
+    /// Refers to [`crate::nowhere`], which doesn't exist.
+    pub fn method() {}
+}
@@ -0,0 +1,36 @@
+extern crate assert_cmd;
+
+use assert_cmd::prelude::*;
+use predicates::prelude::PredicateBooleanExt;
+use predicates::str::contains;
+use std::process::Command;
+
+#[test]
+fn checks_links_to_the_site_locally() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--base-url", "https://example.com/docs/"])
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir("./tests/site_root")
+        .assert()
+        .failure()
+        .stdout(
+            contains("Linked file at path struct.Gone.html does not exist!")
+                .and(contains(
+                    "Linked file at path struct.Missing.html does not exist!",
+                ))
+                .and(contains("struct.Exists.html").not())
+                .and(contains("blog").not()),
+        );
+}
+
+#[test]
+fn rejects_relative_site_root() {
+    Command::cargo_bin("cargo-deadlinks")
+        .unwrap()
+        .args(["deadlinks", "--site-root", "docs/"])
+        .current_dir("./tests/site_root")
+        .assert()
+        .failure()
+        .stderr(contains("the site root must be an absolute path"));
+}
@@ -0,0 +1,8 @@
+//! This documentation is deployed at `https://example.com/docs/`.
+//!
+//! [This struct](https://example.com/docs/site_root/struct.Exists.html) exists,
+//! but [this one](https://example.com/docs/site_root/struct.Gone.html) doesn't.
+//! Neither does [this absolute path](/docs/site_root/struct.Missing.html).
+//! [The blog](https://example.com/blog/) isn't part of the documentation.
+
+pub struct Exists;